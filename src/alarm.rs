@@ -0,0 +1,398 @@
+use crate::button::ButtonEvent;
+use crate::color::{AdjustedColor, Color};
+use crate::framebuffer::BackBuffer;
+use crate::led_driver::{FrameRate, ScreenDriver};
+use crate::low_power::{LowPowerDomain, PlatformLowPower};
+use crate::settings::Settings;
+use crate::time::{self, SECONDS_PER_HOUR, SECONDS_PER_MINUTE};
+
+// Alarms are kept in the SNVS low power general purpose registers, next to the SRTC counter, so
+// they survive resets for as long as the coin cell lasts. Every register but the last one, which
+// holds the settings, holds one alarm, and a register that has been cleared by a power loss reads
+// as no alarm.
+
+pub const MAX_ALARMS: usize = 3;
+
+const ENABLED_BIT: u32 = 0b1 << 31;
+const WEEKDAYS_SHIFT: u32 = 16;
+const HOURS_SHIFT: u32 = 8;
+const MINUTES_SHIFT: u32 = 0;
+
+/// A set of days of the week.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Weekdays(u8);
+
+impl Weekdays {
+    pub const MONDAY: Weekdays = Weekdays(0b1 << 0);
+    pub const TUESDAY: Weekdays = Weekdays(0b1 << 1);
+    pub const WEDNESDAY: Weekdays = Weekdays(0b1 << 2);
+    pub const THURSDAY: Weekdays = Weekdays(0b1 << 3);
+    pub const FRIDAY: Weekdays = Weekdays(0b1 << 4);
+    pub const SATURDAY: Weekdays = Weekdays(0b1 << 5);
+    pub const SUNDAY: Weekdays = Weekdays(0b1 << 6);
+
+    pub const WORKDAYS: Weekdays = Weekdays(0b0011111);
+    pub const WEEKEND: Weekdays = Weekdays(0b1100000);
+    pub const EVERY_DAY: Weekdays = Weekdays(0b1111111);
+
+    pub const fn union(self, other: Weekdays) -> Weekdays {
+        Weekdays(self.0 | other.0)
+    }
+
+    /// Whether the day is in the set, where Monday is 0 like in [`time::weekday`].
+    pub const fn contains(self, weekday: u32) -> bool {
+        weekday < 7 && (self.0 >> weekday) & 0b1 != 0
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Alarm {
+    pub hours: u8,
+    pub minutes: u8,
+    pub weekdays: Weekdays,
+}
+
+impl Alarm {
+    const fn encode(&self) -> u32 {
+        ENABLED_BIT
+            | ((self.weekdays.0 as u32) << WEEKDAYS_SHIFT)
+            | ((self.hours as u32) << HOURS_SHIFT)
+            | ((self.minutes as u32) << MINUTES_SHIFT)
+    }
+
+    const fn decode(value: u32) -> Option<Alarm> {
+        let alarm = Alarm {
+            hours: (value >> HOURS_SHIFT) as u8 & 0b11111,
+            minutes: (value >> MINUTES_SHIFT) as u8 & 0b111111,
+            weekdays: Weekdays((value >> WEEKDAYS_SHIFT) as u8 & Weekdays::EVERY_DAY.0),
+        };
+
+        if value & ENABLED_BIT == 0 || alarm.hours >= 24 || alarm.minutes >= 60 {
+            None
+        } else {
+            Some(alarm)
+        }
+    }
+
+    /// Whether the alarm goes off at the start of the minute that the local seconds are in.
+    pub fn matches(&self, seconds: u32) -> bool {
+        let minutes_of_day = (seconds % time::SECONDS_PER_DAY) / SECONDS_PER_MINUTE;
+
+        minutes_of_day == self.hours as u32 * 60 + self.minutes as u32
+            && self.weekdays.contains(time::weekday(seconds))
+    }
+}
+
+enum AlarmState {
+    Idle,
+    Ringing { since: u32 },
+    Snoozed { until: u32 },
+    // fading in ahead of an alarm that rings at the wake time
+    Sunrise { start: u32, wake_time: u32 },
+}
+
+/// Watches the SRTC for alarms, and takes the panel over with a wake animation while one is
+/// ringing. A short press of the button snoozes, and a long press turns the alarm off, also while
+/// it is snoozed.
+///
+/// Ahead of every alarm, the panel also fades in like a sunrise for as long as the settings say.
+/// Any press cancels the sunrise, and the alarm still rings at the end of it.
+pub struct AlarmClock {
+    low_power: PlatformLowPower,
+    state: AlarmState,
+    last_checked_minute: u32,
+    frame: u32,
+}
+
+impl AlarmClock {
+    pub const FRAME_RATE: FrameRate = FrameRate::Fps16;
+    pub const SNOOZE_SECONDS: u32 = 9 * SECONDS_PER_MINUTE;
+    // stops by itself if nobody is around to turn it off
+    pub const RING_SECONDS: u32 = SECONDS_PER_HOUR / 2;
+
+    // the animation flashes through these, with a dark frame in between
+    const WAKE_COLORS: [AdjustedColor; 4] = [
+        Color::from_rgb(255, 0, 0).adjust_for_led(),
+        Color::from_rgb(255, 128, 0).adjust_for_led(),
+        Color::from_rgb(255, 255, 0).adjust_for_led(),
+        Color::from_rgb(255, 255, 255).adjust_for_led(),
+    ];
+    const FLASH_FRAMES: u32 = 4;
+
+    pub const DEFAULT_SUNRISE_MINUTES: u32 = 20;
+    // the sunrise fades evenly through these, from the start until the wake time
+    const SUNRISE_COLORS: [Color; 4] = [
+        Color::from_rgb(0, 0, 0),
+        Color::from_rgb(128, 0, 0),
+        Color::from_rgb(255, 96, 0),
+        Color::from_rgb(255, 200, 140),
+    ];
+
+    pub fn new() -> Self {
+        let low_power = PlatformLowPower::open();
+        // alarms only go off once their minute starts while running
+        let last_checked_minute = low_power.time() / SECONDS_PER_MINUTE;
+
+        Self {
+            low_power,
+            state: AlarmState::Idle,
+            last_checked_minute,
+            frame: 0,
+        }
+    }
+
+    // the alarms are read from the registers on every check, so they can be set from anywhere
+    pub fn alarm(slot: usize) -> Option<Alarm> {
+        Alarm::decode(PlatformLowPower::open().register(slot))
+    }
+
+    pub fn set_alarm(slot: usize, alarm: Option<Alarm>) {
+        let value = alarm.map_or(0, |alarm| alarm.encode());
+        PlatformLowPower::open().set_register(slot, value);
+    }
+
+    pub fn is_ringing(&self) -> bool {
+        matches!(self.state, AlarmState::Ringing { .. })
+    }
+
+    pub fn is_sunrise(&self) -> bool {
+        matches!(self.state, AlarmState::Sunrise { .. })
+    }
+
+    /// Whether [`AlarmClock::render`] should be called instead of the program.
+    pub fn has_panel(&self) -> bool {
+        self.is_ringing() || self.is_sunrise()
+    }
+
+    /// Checks whether an alarm or sunrise should start or stop. Returns
+    /// [`AlarmClock::has_panel`].
+    pub fn update(&mut self) -> bool {
+        let now = self.low_power.time();
+        let minute = now / SECONDS_PER_MINUTE;
+
+        match self.state {
+            AlarmState::Ringing { since } if now.wrapping_sub(since) >= Self::RING_SECONDS => {
+                self.state = AlarmState::Idle;
+            }
+            AlarmState::Snoozed { until } if now >= until => self.ring(now),
+            // the alarm at the end was removed in the meantime
+            AlarmState::Sunrise { wake_time, .. } if now >= wake_time + SECONDS_PER_MINUTE => {
+                self.state = AlarmState::Idle;
+            }
+            _ => {}
+        }
+
+        if minute != self.last_checked_minute {
+            self.last_checked_minute = minute;
+
+            let settings = Settings::load();
+            let sunrise_seconds = settings.sunrise_minutes * SECONDS_PER_MINUTE;
+            let mut alarm_matches = false;
+            let mut sunrise_matches = false;
+            for alarm in (0..MAX_ALARMS).filter_map(Self::alarm) {
+                alarm_matches |= alarm.matches(settings.time_zone.to_local_seconds(now));
+                sunrise_matches |= sunrise_seconds != 0
+                    && alarm.matches(settings.time_zone.to_local_seconds(now + sunrise_seconds));
+            }
+
+            let minute_start = minute * SECONDS_PER_MINUTE;
+            match self.state {
+                AlarmState::Ringing { .. } => {}
+                _ if alarm_matches => self.ring(now),
+                AlarmState::Idle if sunrise_matches => {
+                    self.state = AlarmState::Sunrise {
+                        start: minute_start,
+                        wake_time: minute_start + sunrise_seconds,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        self.has_panel()
+    }
+
+    pub fn render<const WIDTH: usize, const HEIGHT: usize>(
+        &mut self,
+        driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+    ) {
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        back_buffer.clear();
+
+        if let AlarmState::Sunrise { start, wake_time } = self.state {
+            fill(
+                back_buffer,
+                self.sunrise_color(start, wake_time).adjust_for_led(),
+            );
+            return;
+        }
+
+        let flash = self.frame / Self::FLASH_FRAMES;
+        if flash % 2 == 0 {
+            fill(
+                back_buffer,
+                Self::WAKE_COLORS[(flash / 2) as usize % Self::WAKE_COLORS.len()],
+            );
+        }
+
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// Returns whether the event was used, which is the case for every event while the alarm has
+    /// the panel, and for a long press while it is snoozed.
+    pub fn handle_button(&mut self, event: ButtonEvent) -> bool {
+        self.state = match (&self.state, event) {
+            (AlarmState::Ringing { .. }, ButtonEvent::ShortPress) => AlarmState::Snoozed {
+                until: self.low_power.time() + Self::SNOOZE_SECONDS,
+            },
+            (AlarmState::Ringing { .. } | AlarmState::Snoozed { .. }, ButtonEvent::LongPress) => {
+                AlarmState::Idle
+            }
+            // the alarm matches by itself once the wake time comes
+            (AlarmState::Sunrise { .. }, _) => AlarmState::Idle,
+            _ => return false,
+        };
+
+        true
+    }
+
+    fn sunrise_color(&self, start: u32, wake_time: u32) -> Color {
+        let (seconds, micros) = self.low_power.time_with_micros();
+        let duration = (wake_time - start) as u64 * 1_000_000;
+        let elapsed =
+            (seconds.saturating_sub(start) as u64 * 1_000_000 + micros as u64).min(duration);
+
+        // position along the colors, in 256ths of the way from one to the next
+        let last_segment = Self::SUNRISE_COLORS.len() - 2;
+        let position = (elapsed * (last_segment as u64 + 1) * 256 / duration) as u32;
+        let segment = ((position / 256) as usize).min(last_segment);
+        let fraction = position - segment as u32 * 256;
+
+        let from = Self::SUNRISE_COLORS[segment];
+        let to = Self::SUNRISE_COLORS[segment + 1];
+        Color::from_rgb(
+            mix_channel(from.r, to.r, fraction),
+            mix_channel(from.g, to.g, fraction),
+            mix_channel(from.b, to.b, fraction),
+        )
+    }
+
+    fn ring(&mut self, now: u32) {
+        self.state = AlarmState::Ringing { since: now };
+        self.frame = 0;
+    }
+}
+
+fn fill<const WIDTH: usize, const HEIGHT: usize>(
+    back_buffer: &mut BackBuffer<WIDTH, HEIGHT>,
+    color: AdjustedColor,
+) {
+    back_buffer.fill_rect(
+        0,
+        0,
+        back_buffer.width() as i32,
+        back_buffer.height() as i32,
+        color,
+    );
+}
+
+// the fraction goes from 0 to 256
+fn mix_channel(from: u8, to: u8, fraction: u32) -> u8 {
+    ((from as u32 * (256 - fraction) + to as u32 * fraction) / 256) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::TimeZone;
+
+    // Monday the 1st of January 2024, at midnight UTC
+    const MONDAY: u32 = 1_704_067_200;
+
+    fn set_up(sunrise_minutes: u32, seconds_of_day: u32) -> (AlarmClock, PlatformLowPower) {
+        let mut settings = Settings::load();
+        settings.time_zone = TimeZone::UTC;
+        settings.sunrise_minutes = sunrise_minutes;
+        settings.save();
+
+        AlarmClock::set_alarm(
+            0,
+            Some(Alarm {
+                hours: 7,
+                minutes: 0,
+                weekdays: Weekdays::WORKDAYS,
+            }),
+        );
+
+        let mut low_power = PlatformLowPower::open();
+        low_power.set_time(MONDAY + seconds_of_day);
+
+        (AlarmClock::new(), low_power)
+    }
+
+    fn advance_seconds(low_power: &mut PlatformLowPower, seconds: u32) {
+        low_power.advance_micros(seconds as u64 * 1_000_000);
+    }
+
+    #[test]
+    fn alarms_survive_in_the_registers() {
+        let alarm = Alarm {
+            hours: 23,
+            minutes: 59,
+            weekdays: Weekdays::WEEKEND.union(Weekdays::MONDAY),
+        };
+        AlarmClock::set_alarm(2, Some(alarm));
+
+        assert!(AlarmClock::alarm(2) == Some(alarm));
+        assert!(AlarmClock::alarm(1).is_none());
+
+        AlarmClock::set_alarm(2, None);
+        assert!(AlarmClock::alarm(2).is_none());
+    }
+
+    #[test]
+    fn rings_snoozes_and_turns_off() {
+        let (mut alarm_clock, mut low_power) = set_up(0, 7 * SECONDS_PER_HOUR - 30);
+
+        assert!(!alarm_clock.update());
+        advance_seconds(&mut low_power, 30);
+        assert!(alarm_clock.update());
+        assert!(alarm_clock.is_ringing());
+
+        assert!(alarm_clock.handle_button(ButtonEvent::ShortPress));
+        assert!(!alarm_clock.update());
+        advance_seconds(&mut low_power, AlarmClock::SNOOZE_SECONDS);
+        assert!(alarm_clock.update());
+
+        assert!(alarm_clock.handle_button(ButtonEvent::LongPress));
+        assert!(!alarm_clock.update());
+        // nothing left to use the button for
+        assert!(!alarm_clock.handle_button(ButtonEvent::LongPress));
+    }
+
+    #[test]
+    fn stays_quiet_on_other_days() {
+        // a Saturday, which the alarm doesn't go off on
+        let (mut alarm_clock, mut low_power) = set_up(0, 5 * time::SECONDS_PER_DAY);
+        advance_seconds(&mut low_power, 7 * SECONDS_PER_HOUR);
+
+        assert!(!alarm_clock.update());
+    }
+
+    #[test]
+    fn cancelled_sunrise_still_rings() {
+        let (mut alarm_clock, mut low_power) = set_up(20, 7 * SECONDS_PER_HOUR - 20 * 60 - 10);
+
+        assert!(!alarm_clock.update());
+        advance_seconds(&mut low_power, 10);
+        assert!(alarm_clock.update());
+        assert!(alarm_clock.is_sunrise());
+
+        assert!(alarm_clock.handle_button(ButtonEvent::ShortPress));
+        assert!(!alarm_clock.update());
+
+        advance_seconds(&mut low_power, 20 * SECONDS_PER_MINUTE);
+        assert!(alarm_clock.update());
+        assert!(alarm_clock.is_ringing());
+    }
+}
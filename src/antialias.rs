@@ -0,0 +1,347 @@
+use crate::color::{AdjustedColor, AdjustedColorRgba, BlendMode};
+use crate::draw::isqrt;
+use crate::framebuffer::BackBuffer;
+
+// Anti-aliased drawing with sub-pixel coordinates. Positions are fixed point numbers with
+// SUBPIXEL_BITS fractional bits, and whole numbers are the pixel centers, like the integer
+// coordinates everywhere else. Partially covered pixels get the color blended over them by their
+// coverage, so shapes can move by less than a pixel at a time.
+//
+// Anything that adds or subtracts positions is done in i64, so coordinates anywhere in the i32
+// range can be drawn, and only the part over the buffer gets walked.
+
+pub const SUBPIXEL_BITS: u32 = 8;
+pub const SUBPIXEL_ONE: i32 = 1 << SUBPIXEL_BITS;
+
+const SUBPIXEL_HALF: i32 = SUBPIXEL_ONE / 2;
+const SUBPIXEL_MASK: i32 = SUBPIXEL_ONE - 1;
+
+/// Converts whole pixels into sub-pixel coordinates.
+pub const fn subpixels(pixels: i32) -> i32 {
+    pixels << SUBPIXEL_BITS
+}
+
+// the pixel that the position lies in, rounding towards negative infinity
+const fn whole_part(value: i32) -> i32 {
+    value >> SUBPIXEL_BITS
+}
+
+const fn fraction(value: i32) -> i32 {
+    value & SUBPIXEL_MASK
+}
+
+const fn inverse_fraction(value: i32) -> i32 {
+    SUBPIXEL_ONE - fraction(value)
+}
+
+// the nearest pixel, taking wider positions than the others since it's used on sums of them
+const fn round(value: i64) -> i64 {
+    (value + SUBPIXEL_HALF as i64) >> SUBPIXEL_BITS
+}
+
+// multiplies two sub-pixel values
+const fn multiply(a: i32, b: i32) -> i32 {
+    ((a as i64 * b as i64) >> SUBPIXEL_BITS) as i32
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> BackBuffer<WIDTH, HEIGHT> {
+    /// Draws a single pixel sized dot, which gets spread over the four pixels it overlaps.
+    pub fn draw_dot(&mut self, x: i32, y: i32, color: impl Into<AdjustedColor>) {
+        let color = color.into();
+
+        let left = whole_part(x) as i64;
+        let top = whole_part(y) as i64;
+        let right_coverage = fraction(x);
+        let bottom_coverage = fraction(y);
+        let left_coverage = SUBPIXEL_ONE - right_coverage;
+        let top_coverage = SUBPIXEL_ONE - bottom_coverage;
+
+        self.plot_coverage(left, top, color, multiply(left_coverage, top_coverage));
+        self.plot_coverage(left + 1, top, color, multiply(right_coverage, top_coverage));
+        self.plot_coverage(
+            left,
+            top + 1,
+            color,
+            multiply(left_coverage, bottom_coverage),
+        );
+        self.plot_coverage(
+            left + 1,
+            top + 1,
+            color,
+            multiply(right_coverage, bottom_coverage),
+        );
+    }
+
+    /// Xiaolin Wu's line algorithm, with sub-pixel end points.
+    pub fn draw_line_smooth(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        let color = color.into();
+        let (mut x0, mut y0, mut x1, mut y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+
+        // walk along the longer axis
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            core::mem::swap(&mut x0, &mut y0);
+            core::mem::swap(&mut x1, &mut y1);
+        }
+        if x0 > x1 {
+            core::mem::swap(&mut x0, &mut x1);
+            core::mem::swap(&mut y0, &mut y1);
+        }
+
+        let delta_x = x1 - x0;
+        let delta_y = y1 - y0;
+        if delta_x == 0 {
+            // both ends are at the same spot
+            // the positions came in as i32, so they still fit
+            let (x, y) = if steep { (y0, x0) } else { (x0, y0) };
+            self.draw_dot(x as i32, y as i32, color);
+            return;
+        }
+        let gradient = (delta_y << SUBPIXEL_BITS) / delta_x;
+        let (long_side, across_side) = if steep {
+            (self.height(), self.width())
+        } else {
+            (self.width(), self.height())
+        };
+        let long_side = long_side as i64;
+
+        // where the line crosses the center of a pixel along it. anything more than a pixel off of
+        // the buffer gets clipped anyway, so it's clamped to stay in range
+        let across_limit = subpixels(across_side as i32 + 1) as i64;
+        let across_at = |along: i64, from_along: i64, from_across: i64| -> i32 {
+            let offset = subpixels(1) as i64 * along - from_along;
+            let across = from_across + ((gradient * offset) >> SUBPIXEL_BITS);
+            across.clamp(-across_limit, across_limit) as i32
+        };
+        let plot = |buffer: &mut Self, along: i64, across: i32, coverage: i32| {
+            if steep {
+                buffer.plot_coverage(across as i64, along, color, coverage);
+            } else {
+                buffer.plot_coverage(along, across as i64, color, coverage);
+            }
+        };
+
+        // the end pixels are only covered by the part of the line that reaches into them
+        let first = (x0 + SUBPIXEL_HALF as i64) >> SUBPIXEL_BITS;
+        let last = (x1 + SUBPIXEL_HALF as i64) >> SUBPIXEL_BITS;
+        // only the fractions are needed, which survive the truncation
+        for (end, end_x, end_y, gap) in [
+            (
+                first,
+                x0,
+                y0,
+                inverse_fraction((x0 + SUBPIXEL_HALF as i64) as i32),
+            ),
+            (last, x1, y1, fraction((x1 + SUBPIXEL_HALF as i64) as i32)),
+        ] {
+            if (0..long_side).contains(&end) {
+                let y = across_at(end, end_x, end_y);
+                plot(self, end, whole_part(y), multiply(inverse_fraction(y), gap));
+                plot(self, end, whole_part(y) + 1, multiply(fraction(y), gap));
+            }
+
+            if first == last {
+                break;
+            }
+        }
+
+        // only the part of the line over the buffer is walked
+        for along in (first + 1).max(0)..last.min(long_side) {
+            let y = across_at(along, x0, y0);
+            plot(self, along, whole_part(y), inverse_fraction(y));
+            plot(self, along, whole_part(y) + 1, fraction(y));
+        }
+    }
+
+    /// A circle outline one pixel wide, with a sub-pixel center and radius.
+    pub fn draw_circle_smooth(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        if radius < 0 {
+            return;
+        }
+
+        // pixels fade out as their distance from the circle approaches a pixel
+        let radius = radius as i64;
+        self.circle_coverage(
+            center_x,
+            center_y,
+            radius + SUBPIXEL_ONE as i64,
+            color.into(),
+            |distance| SUBPIXEL_ONE as i64 - (distance - radius).abs(),
+        );
+    }
+
+    /// A filled circle with a sub-pixel center and radius, with soft edges.
+    pub fn fill_circle_smooth(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        if radius < 0 {
+            return;
+        }
+
+        let radius = radius as i64;
+        self.circle_coverage(
+            center_x,
+            center_y,
+            radius + SUBPIXEL_ONE as i64,
+            color.into(),
+            |distance| radius + SUBPIXEL_HALF as i64 - distance,
+        );
+    }
+
+    // calls the coverage function with the distance of every pixel within the extent from the
+    // center, and plots the pixels it returns a positive coverage for
+    fn circle_coverage<F: Fn(i64) -> i64>(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        extent: i64,
+        color: AdjustedColor,
+        coverage: F,
+    ) {
+        let (center_x, center_y) = (center_x as i64, center_y as i64);
+
+        let left = ((center_x - extent) >> SUBPIXEL_BITS).max(0);
+        let right = round(center_x + extent).min(self.width() as i64 - 1);
+        let top = ((center_y - extent) >> SUBPIXEL_BITS).max(0);
+        let bottom = round(center_y + extent).min(self.height() as i64 - 1);
+
+        for y in top..=bottom {
+            let offset_y = ((y << SUBPIXEL_BITS) - center_y) as i128;
+
+            for x in left..=right {
+                let offset_x = ((x << SUBPIXEL_BITS) - center_x) as i128;
+                // the squares of offsets across the whole i32 range add up past an i64
+                let distance = isqrt((offset_x * offset_x + offset_y * offset_y) as u128) as i64;
+                let pixel_coverage = coverage(distance).clamp(0, SUBPIXEL_ONE as i64) as i32;
+
+                self.plot_coverage(x, y, color, pixel_coverage);
+            }
+        }
+    }
+
+    // blends the color over the pixel by how much of it is covered, and clips
+    fn plot_coverage(&mut self, x: i64, y: i64, color: AdjustedColor, coverage: i32) {
+        if coverage <= 0 || x < 0 || y < 0 || x >= self.width() as i64 || y >= self.height() as i64
+        {
+            return;
+        }
+
+        let alpha =
+            ((coverage.min(SUBPIXEL_ONE) * u8::MAX as i32 + SUBPIXEL_HALF) >> SUBPIXEL_BITS) as u8;
+        self.blend_led(
+            x as usize,
+            y as usize,
+            AdjustedColorRgba::from_color(color, alpha),
+            BlendMode::Normal,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{PANEL_HEIGHT, PANEL_WIDTH};
+
+    const ON: AdjustedColor = AdjustedColor {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    #[test]
+    fn long_lines_only_walk_the_buffer() {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        buffer.draw_line_smooth(i32::MIN, subpixels(2), i32::MAX, subpixels(2), ON);
+        buffer.draw_line_smooth(i32::MIN, i32::MIN, i32::MAX, i32::MAX, ON);
+
+        // the flat line goes through the pixel centers, so the whole row is covered
+        for x in 0..PANEL_WIDTH {
+            assert!(buffer.get_led_adjusted(x, 2) == ON);
+        }
+    }
+
+    #[test]
+    fn dots_spread_over_the_pixels_they_overlap() {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        // right on a pixel center
+        buffer.draw_dot(subpixels(3), subpixels(4), ON);
+        assert!(buffer.get_led_adjusted(3, 4) == ON);
+        assert!(buffer.get_led_adjusted(4, 4) == AdjustedColor::default());
+        assert!(buffer.get_led_adjusted(3, 5) == AdjustedColor::default());
+
+        // halfway between four of them
+        buffer.draw_dot(
+            subpixels(6) + SUBPIXEL_HALF,
+            subpixels(5) + SUBPIXEL_HALF,
+            ON,
+        );
+        let quarter = buffer.get_led_adjusted(6, 5);
+        assert!(quarter != AdjustedColor::default() && quarter != ON);
+        for (x, y) in [(7, 5), (6, 6), (7, 6)] {
+            assert!(buffer.get_led_adjusted(x, y) == quarter);
+        }
+    }
+
+    #[test]
+    fn smooth_circles_fade_at_their_edge() {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        buffer.fill_circle_smooth(subpixels(5), subpixels(4), subpixels(3), ON);
+
+        assert!(buffer.get_led_adjusted(5, 4) == ON);
+        assert!(buffer.get_led_adjusted(7, 4) == ON);
+        // the edge runs through the middle of these
+        let edge = buffer.get_led_adjusted(8, 4);
+        assert!(edge != AdjustedColor::default() && edge != ON);
+        assert!(buffer.get_led_adjusted(5, 1) == edge);
+        assert!(buffer.get_led_adjusted(9, 4) == AdjustedColor::default());
+
+        let mut buffer: BackBuffer = BackBuffer::default();
+        buffer.draw_circle_smooth(subpixels(5), subpixels(4), subpixels(3), ON);
+        assert!(buffer.get_led_adjusted(8, 4) == ON);
+        assert!(buffer.get_led_adjusted(5, 4) == AdjustedColor::default());
+    }
+
+    #[test]
+    fn extreme_positions_only_walk_the_buffer() {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        for (x, y) in [
+            (i32::MIN, i32::MIN),
+            (i32::MAX, i32::MAX),
+            (i32::MIN, i32::MAX),
+        ] {
+            buffer.draw_dot(x, y, ON);
+            buffer.draw_circle_smooth(x, y, i32::MAX, ON);
+            buffer.fill_circle_smooth(x, y, i32::MAX, ON);
+        }
+        for y in 0..PANEL_HEIGHT {
+            for x in 0..PANEL_WIDTH {
+                assert!(buffer.get_led_adjusted(x, y) == AdjustedColor::default());
+            }
+        }
+
+        // a circle as big as it gets covers everything
+        buffer.fill_circle_smooth(0, 0, i32::MAX, ON);
+        for y in 0..PANEL_HEIGHT {
+            for x in 0..PANEL_WIDTH {
+                assert!(buffer.get_led_adjusted(x, y) == ON);
+            }
+        }
+    }
+}
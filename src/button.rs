@@ -1,21 +1,44 @@
+#[cfg(target_arch = "arm")]
 use cortex_m::peripheral::DWT;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::board::ARM_FREQUENCY;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::imxrt_iomuxc::gpio::Pin;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::imxrt_iomuxc::ErasedPad;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::tmm::P5;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::ral::{self, read_reg};
 
+#[cfg(target_arch = "arm")]
 use crate::peripherals;
+#[cfg(target_arch = "arm")]
 use crate::pins::button_pin_setup;
 
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ButtonEvent {
+    /// The button was released before it counted as a long press.
+    ShortPress,
+    /// The button has been held down for a second. Sent while it is still
+    /// held, and the release afterwards doesn't count as a short press.
+    LongPress,
+}
+
+#[cfg(target_arch = "arm")]
 pub struct Button {
     last_button_input_time: u32,
     last_set_value: bool,
     debounce_value: bool,
+    pressed_time: u32,
+    long_press_sent: bool,
 }
 
+#[cfg(target_arch = "arm")]
 impl Button {
     pub const BUTTON_DEBOUNCE_DELAY: u32 = ARM_FREQUENCY / 50;
+    // has to stay below the cycle counter period, which is a little over 7 seconds
+    pub const LONG_PRESS_DELAY: u32 = ARM_FREQUENCY;
 
     pub fn new(pin_5: &mut ErasedPad) -> Self {
         button_pin_setup(pin_5, P5::OFFSET);
@@ -24,23 +47,41 @@ impl Button {
             last_button_input_time: 0,
             last_set_value: false,
             debounce_value: false,
+            pressed_time: 0,
+            long_press_sent: false,
         }
     }
 
-    pub fn check_if_pressed<F: FnOnce()>(&mut self, true_eval: F) {
+    /// Samples the button, at most once per debounce delay. Has to be called regularly, since
+    /// presses are only picked up when they span two samples.
+    pub fn poll(&mut self) -> Option<ButtonEvent> {
         let current_cycles = DWT::cycle_count();
-        if current_cycles.wrapping_sub(self.last_button_input_time) >= Self::BUTTON_DEBOUNCE_DELAY {
-            let button_read_value =
-                (read_reg!(ral::gpio, peripherals::gpio9(), PSR) & (1 << P5::OFFSET)) != 0;
-            let button_pushed = button_read_value && self.debounce_value;
-
-            if button_pushed && !self.last_set_value {
-                true_eval();
-            }
-
-            self.last_set_value = button_pushed;
-            self.debounce_value = button_read_value;
-            self.last_button_input_time = current_cycles;
+        if current_cycles.wrapping_sub(self.last_button_input_time) < Self::BUTTON_DEBOUNCE_DELAY {
+            return None;
         }
+
+        let button_read_value =
+            (read_reg!(ral::gpio, peripherals::gpio9(), PSR) & (1 << P5::OFFSET)) != 0;
+        let button_pushed = button_read_value && self.debounce_value;
+
+        let mut event = None;
+        if button_pushed && !self.last_set_value {
+            self.pressed_time = current_cycles;
+            self.long_press_sent = false;
+        } else if button_pushed
+            && !self.long_press_sent
+            && current_cycles.wrapping_sub(self.pressed_time) >= Self::LONG_PRESS_DELAY
+        {
+            self.long_press_sent = true;
+            event = Some(ButtonEvent::LongPress);
+        } else if !button_pushed && self.last_set_value && !self.long_press_sent {
+            event = Some(ButtonEvent::ShortPress);
+        }
+
+        self.last_set_value = button_pushed;
+        self.debounce_value = button_read_value;
+        self.last_button_input_time = current_cycles;
+
+        event
     }
 }
@@ -11,10 +11,20 @@ impl Color {
     }
 
     pub const fn adjust_for_led(self) -> AdjustedColor {
+        self.adjust_for_led_with(&GAMMA_LUT, &LED_CALIBRATION)
+    }
+
+    /// Same as [`Color::adjust_for_led`], but with a different gamma curve or calibration than
+    /// [`GAMMA_CURVE`] and [`LED_CALIBRATION`].
+    pub const fn adjust_for_led_with(
+        self,
+        gamma_lut: &GammaLut,
+        calibration: &LedCalibration,
+    ) -> AdjustedColor {
         AdjustedColor {
-            r: self.r,
-            g: ((self.g as u16 * 29) / 50) as u8,
-            b: ((self.b as u16 * 29) / 70) as u8,
+            r: calibration.r.apply(gamma_lut.apply(self.r)),
+            g: calibration.g.apply(gamma_lut.apply(self.g)),
+            b: calibration.b.apply(gamma_lut.apply(self.b)),
         }
     }
 }
@@ -26,4 +36,419 @@ pub struct AdjustedColor {
     pub b: u8,
 }
 
+impl AdjustedColor {
+    /// Approximately reverses [`Color::adjust_for_led`]. The gamma curve squeezes many dark colors
+    /// into the same adjusted value, so those come back as a color somewhere in the middle of the
+    /// range that they could have been.
+    pub const fn to_color(self) -> Color {
+        Color {
+            r: INVERSE_LUTS[0].apply(self.r),
+            g: INVERSE_LUTS[1].apply(self.g),
+            b: INVERSE_LUTS[2].apply(self.b),
+        }
+    }
+}
+
+impl From<Color> for AdjustedColor {
+    fn from(color: Color) -> Self {
+        color.adjust_for_led()
+    }
+}
+
 pub const BLACK: Color = Color::from_rgb(0, 0, 0);
+pub const WHITE: Color = Color::from_rgb(255, 255, 255);
+
+/// A color with an alpha channel, where 0 is fully transparent and [`u8::MAX`] is opaque.
+#[derive(Default, Eq, PartialEq, Copy, Clone)]
+pub struct ColorRgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl ColorRgba {
+    pub const fn from_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn from_color(color: Color, a: u8) -> Self {
+        Self::from_rgba(color.r, color.g, color.b, a)
+    }
+
+    /// Adjusts the color channels like [`Color::adjust_for_led`]. Alpha is left alone, so blending
+    /// happens on the adjusted values, which are linear in light.
+    pub const fn adjust_for_led(self) -> AdjustedColorRgba {
+        AdjustedColorRgba::from_color(
+            Color::from_rgb(self.r, self.g, self.b).adjust_for_led(),
+            self.a,
+        )
+    }
+}
+
+#[derive(Default, Eq, PartialEq, Copy, Clone)]
+pub struct AdjustedColorRgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl AdjustedColorRgba {
+    pub const fn from_color(color: AdjustedColor, a: u8) -> Self {
+        Self {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+            a,
+        }
+    }
+
+    pub const fn color(self) -> AdjustedColor {
+        AdjustedColor {
+            r: self.r,
+            g: self.g,
+            b: self.b,
+        }
+    }
+}
+
+impl From<ColorRgba> for AdjustedColorRgba {
+    fn from(color: ColorRgba) -> Self {
+        color.adjust_for_led()
+    }
+}
+
+impl From<AdjustedColor> for AdjustedColorRgba {
+    fn from(color: AdjustedColor) -> Self {
+        Self::from_color(color, u8::MAX)
+    }
+}
+
+impl From<Color> for AdjustedColorRgba {
+    fn from(color: Color) -> Self {
+        Self::from_color(color.adjust_for_led(), u8::MAX)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum BlendMode {
+    /// Paints over the destination.
+    Normal,
+    /// Adds the source to the destination.
+    Additive,
+    /// Darkens the destination by the source, white leaves it unchanged.
+    Multiply,
+    /// Lightens the destination by the source, black leaves it unchanged.
+    Screen,
+    /// Keeps the brighter of the two, per channel.
+    Max,
+}
+
+impl BlendMode {
+    // Adjusted channels don't all reach u8::MAX, so everything is relative to the calibrated
+    // white. Otherwise, multiplying by white would tint the destination.
+    const CHANNEL_MAX: AdjustedColor = WHITE.adjust_for_led();
+
+    /// Blends the source over the destination, and then mixes that with the destination by the
+    /// source alpha.
+    pub fn blend(self, destination: AdjustedColor, source: AdjustedColorRgba) -> AdjustedColor {
+        AdjustedColor {
+            r: self.blend_channel(destination.r, source.r, source.a, Self::CHANNEL_MAX.r),
+            g: self.blend_channel(destination.g, source.g, source.a, Self::CHANNEL_MAX.g),
+            b: self.blend_channel(destination.b, source.b, source.a, Self::CHANNEL_MAX.b),
+        }
+    }
+
+    fn blend_channel(self, destination: u8, source: u8, alpha: u8, channel_max: u8) -> u8 {
+        let destination = destination as u32;
+        let source = source as u32;
+        let channel_max = (channel_max as u32).max(1);
+
+        let blended = match self {
+            BlendMode::Normal => source,
+            BlendMode::Additive => (destination + source).min(channel_max.max(destination)),
+            BlendMode::Multiply => (destination * source) / channel_max,
+            BlendMode::Screen => {
+                let inverse = channel_max.saturating_sub(destination)
+                    * channel_max.saturating_sub(source)
+                    / channel_max;
+                channel_max.saturating_sub(inverse).max(destination)
+            }
+            BlendMode::Max => destination.max(source),
+        };
+
+        // mix with rounding, so full alpha always gives the blended value
+        let alpha = alpha as u32;
+        ((blended * alpha + destination * (u8::MAX as u32 - alpha) + (u8::MAX as u32 / 2))
+            / u8::MAX as u32) as u8
+    }
+}
+
+/// How a single color channel of the LEDs gets driven, to make up for differences between LED
+/// bins. Values are applied after the gamma curve.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct ChannelCalibration {
+    /// The relative brightness of the channel, as a fraction which should be at most 1. This is
+    /// what sets the white balance.
+    pub scale_numerator: u16,
+    pub scale_denominator: u16,
+    /// The lowest duty cycle at which the LED visibly lights up. Lit values are spread out above it.
+    pub offset: u8,
+    /// The highest duty cycle the channel is driven at, as a fraction of [`u8::MAX`].
+    pub max_current_ratio: u8,
+}
+
+impl ChannelCalibration {
+    pub const UNCHANGED: ChannelCalibration = ChannelCalibration::scaled(1, 1);
+
+    pub const fn scaled(scale_numerator: u16, scale_denominator: u16) -> Self {
+        Self {
+            scale_numerator,
+            scale_denominator,
+            offset: 0,
+            max_current_ratio: u8::MAX,
+        }
+    }
+
+    pub const fn apply(&self, value: u8) -> u8 {
+        if value == 0 {
+            return 0;
+        }
+
+        let scaled = (value as u32 * self.scale_numerator as u32) / self.scale_denominator as u32;
+        let range = self.max_current_ratio.saturating_sub(self.offset) as u32;
+
+        (self.offset as u32 + (scaled * range) / u8::MAX as u32) as u8
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct LedCalibration {
+    pub r: ChannelCalibration,
+    pub g: ChannelCalibration,
+    pub b: ChannelCalibration,
+}
+
+impl LedCalibration {
+    /// Measured on the LEDs of the original matrix.
+    pub const ORIGINAL_BATCH: LedCalibration = LedCalibration {
+        r: ChannelCalibration::UNCHANGED,
+        g: ChannelCalibration::scaled(29, 50),
+        b: ChannelCalibration::scaled(29, 70),
+    };
+
+    pub const UNCALIBRATED: LedCalibration = LedCalibration {
+        r: ChannelCalibration::UNCHANGED,
+        g: ChannelCalibration::UNCHANGED,
+        b: ChannelCalibration::UNCHANGED,
+    };
+}
+
+/// The calibration that [`Color::adjust_for_led`] uses. The white balance test pattern program
+/// helps with tuning it for other LEDs.
+pub const LED_CALIBRATION: LedCalibration = LedCalibration::ORIGINAL_BATCH;
+const _: () = {
+    let channels = [LED_CALIBRATION.r, LED_CALIBRATION.g, LED_CALIBRATION.b];
+
+    let mut i = 0;
+    while i < channels.len() {
+        assert!(channels[i].scale_denominator != 0);
+        assert!(channels[i].scale_numerator <= channels[i].scale_denominator);
+        i += 1;
+    }
+};
+
+/// The transfer curve from 8-bit colors to PWM duty cycles. The PWM output is linear in light,
+/// so without a curve the mid-tones come out far too bright.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum GammaCurve {
+    Linear,
+    /// A pure power curve, with the exponent given as a fraction.
+    Power {
+        numerator: u32,
+        denominator: u32,
+    },
+    /// The piecewise sRGB curve.
+    Srgb,
+}
+
+impl GammaCurve {
+    pub const GAMMA_1_8: GammaCurve = GammaCurve::Power {
+        numerator: 9,
+        denominator: 5,
+    };
+    pub const GAMMA_2_2: GammaCurve = GammaCurve::Power {
+        numerator: 11,
+        denominator: 5,
+    };
+    pub const GAMMA_2_8: GammaCurve = GammaCurve::Power {
+        numerator: 14,
+        denominator: 5,
+    };
+
+    /// Maps a value from 0 to 1 through the curve, both in fixed point.
+    const fn apply_fixed(&self, value: u128) -> u128 {
+        match *self {
+            GammaCurve::Linear => value,
+            GammaCurve::Power {
+                numerator,
+                denominator,
+            } => fixed_root(fixed_pow(value, numerator), denominator),
+            GammaCurve::Srgb => {
+                // 0.04045, below which the curve is linear
+                let threshold = (FIXED_ONE * 4045) / 100_000;
+
+                if value <= threshold {
+                    (value * 100) / 1292
+                } else {
+                    // ((value + 0.055) / 1.055) ^ 2.4
+                    let shifted = ((value + (FIXED_ONE * 55) / 1000) * 1000) / 1055;
+                    fixed_root(fixed_pow(shifted, 12), 5)
+                }
+            }
+        }
+    }
+}
+
+/// The curve that [`Color::adjust_for_led`] uses.
+pub const GAMMA_CURVE: GammaCurve = GammaCurve::GAMMA_2_2;
+pub const GAMMA_LUT: GammaLut = GammaLut::new(GAMMA_CURVE);
+
+/// A gamma curve sampled for every 8-bit value. Only meant to be built at compile time.
+pub struct GammaLut {
+    values: [u8; 256],
+}
+
+impl GammaLut {
+    pub const fn new(curve: GammaCurve) -> Self {
+        let mut values = [0; 256];
+
+        let mut i = 0;
+        while i < values.len() {
+            let value = (i as u128 * FIXED_ONE) / u8::MAX as u128;
+            let mapped = curve.apply_fixed(value);
+            // round to the nearest value
+            values[i] = ((mapped * u8::MAX as u128 + FIXED_ONE / 2) / FIXED_ONE) as u8;
+
+            i += 1;
+        }
+
+        Self { values }
+    }
+
+    pub const fn apply(&self, value: u8) -> u8 {
+        self.values[value as usize]
+    }
+}
+
+const INVERSE_LUTS: [InverseLut; 3] = [
+    InverseLut::new(&GAMMA_LUT, &LED_CALIBRATION.r),
+    InverseLut::new(&GAMMA_LUT, &LED_CALIBRATION.g),
+    InverseLut::new(&GAMMA_LUT, &LED_CALIBRATION.b),
+];
+
+/// Maps the adjusted values of a single channel back to the closest color values, for
+/// [`AdjustedColor::to_color`]. Only meant to be built at compile time.
+pub struct InverseLut {
+    values: [u8; 256],
+}
+
+impl InverseLut {
+    pub const fn new(gamma_lut: &GammaLut, calibration: &ChannelCalibration) -> Self {
+        let mut forward = [0; 256];
+        let mut i = 0;
+        while i < forward.len() {
+            forward[i] = calibration.apply(gamma_lut.apply(i as u8));
+            i += 1;
+        }
+
+        let mut values = [0; 256];
+        let mut adjusted = 0;
+        while adjusted < values.len() {
+            // the forward mapping never goes down, so every adjusted value comes from a single
+            // range of colors, which starts at the first color that maps to at least that value
+            let start = first_at_least(&forward, adjusted as u8);
+            let mut end = start;
+            while end < forward.len() && forward[end] == adjusted as u8 {
+                end += 1;
+            }
+
+            values[adjusted] = if adjusted == 0 {
+                // keep black black
+                0
+            } else if end > start {
+                ((start + end - 1) / 2) as u8
+            } else if start == 0 {
+                0
+            } else if start == forward.len() {
+                u8::MAX
+            } else if adjusted as u8 - forward[start - 1] <= forward[start] - adjusted as u8 {
+                // not reachable, so take whichever neighbor comes closer
+                (start - 1) as u8
+            } else {
+                start as u8
+            };
+
+            adjusted += 1;
+        }
+
+        Self { values }
+    }
+
+    pub const fn apply(&self, value: u8) -> u8 {
+        self.values[value as usize]
+    }
+}
+
+const fn first_at_least(sorted: &[u8; 256], value: u8) -> usize {
+    let mut low = 0;
+    let mut high = sorted.len();
+
+    while low < high {
+        let middle = (low + high) / 2;
+        if sorted[middle] < value {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    low
+}
+
+// Const-compatible fixed point helpers for the gamma tables, with 48 fractional bits. Values are
+// always between 0 and 1, so the products always fit. The extra precision keeps high powers of
+// small values from flushing to zero too early.
+
+const FIXED_BITS: u32 = 48;
+const FIXED_ONE: u128 = 1 << FIXED_BITS;
+
+const fn fixed_pow(value: u128, exponent: u32) -> u128 {
+    let mut result = FIXED_ONE;
+
+    let mut i = 0;
+    while i < exponent {
+        result = (result * value) >> FIXED_BITS;
+        i += 1;
+    }
+
+    result
+}
+
+// the largest fixed point number that doesn't exceed the value when raised to the degree
+const fn fixed_root(value: u128, degree: u32) -> u128 {
+    let mut result = 0;
+
+    let mut bit = FIXED_BITS;
+    while bit > 0 {
+        bit -= 1;
+
+        let candidate = result | (1 << bit);
+        if fixed_pow(candidate, degree) <= value {
+            result = candidate;
+        }
+    }
+
+    result
+}
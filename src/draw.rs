@@ -0,0 +1,646 @@
+use alloc::vec::Vec;
+
+use crate::color::AdjustedColor;
+use crate::framebuffer::BackBuffer;
+
+// Drawing primitives for the back buffer. Coordinates are signed so shapes can hang off any edge
+// of the panel, and everything outside of it gets clipped. Colors can be either a Color, which
+// gets adjusted for the LEDs, or an already adjusted color.
+
+impl<const WIDTH: usize, const HEIGHT: usize> BackBuffer<WIDTH, HEIGHT> {
+    pub fn draw_pixel(&mut self, x: i32, y: i32, color: impl Into<AdjustedColor>) {
+        self.plot(x as i64, y as i64, color.into());
+    }
+
+    pub fn draw_line(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        self.line(x0, y0, x1, y1, color.into());
+    }
+
+    pub fn draw_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+
+        let color = color.into();
+        let (x, y) = (x as i64, y as i64);
+        let right = x + width as i64 - 1;
+        let bottom = y + height as i64 - 1;
+
+        self.horizontal_span(x, right, y, color);
+        self.horizontal_span(x, right, bottom, color);
+        for edge_y in (y + 1).max(0)..bottom.min(self.height() as i64) {
+            self.plot(x, edge_y, color);
+            self.plot(right, edge_y, color);
+        }
+    }
+
+    pub fn fill_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        let color = color.into();
+        let (x, y) = (x as i64, y as i64);
+        let right = x + width as i64 - 1;
+        let bottom = y + height as i64 - 1;
+
+        for span_y in y.max(0)..=bottom.min(self.height() as i64 - 1) {
+            self.horizontal_span(x, right, span_y, color);
+        }
+    }
+
+    /// Midpoint circle, centered on a pixel.
+    pub fn draw_circle(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        self.draw_ellipse(center_x, center_y, radius, radius, color);
+    }
+
+    pub fn fill_circle(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        self.fill_ellipse(center_x, center_y, radius, radius, color);
+    }
+
+    /// Midpoint ellipse, centered on a pixel and aligned to the axes.
+    pub fn draw_ellipse(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius_x: i32,
+        radius_y: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        if radius_x < 0 || radius_y < 0 {
+            return;
+        }
+
+        let color = color.into();
+        let (center_x, center_y) = (center_x as i64, center_y as i64);
+
+        let Some(quadrant) = EllipseQuadrant::new(radius_x, radius_y) else {
+            // a flat ellipse is a line, which is the same filled or not
+            self.fill_ellipse_rows(center_x, center_y, radius_x, radius_y, color);
+            return;
+        };
+
+        // the shallow part has a pixel per column, so only the columns on the buffer are walked
+        for x in visible_offsets(center_x, self.width(), quadrant.shallow_end - 1) {
+            let y = quadrant.shallow_y(x);
+            for (x, y) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+                self.plot(center_x + x, center_y + y, color);
+            }
+        }
+
+        // the steep part has a run of pixels per row, so only the rows on the buffer are walked
+        for y in visible_offsets(center_y, self.height(), quadrant.steep_end - 1) {
+            let (run_start, run_end) = quadrant.steep_run(y);
+            for y in [y, -y] {
+                self.horizontal_span(
+                    center_x + run_start,
+                    center_x + run_end,
+                    center_y + y,
+                    color,
+                );
+                self.horizontal_span(
+                    center_x - run_end,
+                    center_x - run_start,
+                    center_y + y,
+                    color,
+                );
+            }
+        }
+    }
+
+    pub fn fill_ellipse(
+        &mut self,
+        center_x: i32,
+        center_y: i32,
+        radius_x: i32,
+        radius_y: i32,
+        color: impl Into<AdjustedColor>,
+    ) {
+        if radius_x < 0 || radius_y < 0 {
+            return;
+        }
+
+        let color = color.into();
+        self.fill_ellipse_rows(center_x as i64, center_y as i64, radius_x, radius_y, color);
+    }
+
+    /// Draws the outline of a closed polygon, the last point connects back to the first one.
+    pub fn draw_polygon(&mut self, points: &[(i32, i32)], color: impl Into<AdjustedColor>) {
+        let color = color.into();
+
+        for (i, &(x0, y0)) in points.iter().enumerate() {
+            let (x1, y1) = points[(i + 1) % points.len()];
+            self.line(x0, y0, x1, y1, color);
+        }
+    }
+
+    /// Fills a closed polygon with the even-odd rule. The outline is drawn as well, so thin
+    /// polygons don't disappear.
+    pub fn fill_polygon(&mut self, points: &[(i32, i32)], color: impl Into<AdjustedColor>) {
+        let color = color.into();
+
+        for y in 0..self.height() as i32 {
+            for x in 0..self.width() as i32 {
+                if contains_point(points, x, y) {
+                    self.plot(x as i64, y as i64, color);
+                }
+            }
+        }
+
+        self.draw_polygon(points, color);
+    }
+
+    /// Replaces the 4-connected area of pixels that have the same color as the starting pixel.
+    pub fn flood_fill(&mut self, x: i32, y: i32, color: impl Into<AdjustedColor>) {
+        if !self.in_bounds(x as i64, y as i64) {
+            return;
+        }
+
+        let color = color.into();
+        let target_color = self.get_led_adjusted(x as usize, y as usize);
+        if target_color == color {
+            return;
+        }
+
+        // Scanline fill. Seeds are painted as soon as they're pushed, so no pixel can be pushed
+        // twice, and the pending seeds never outgrow the buffer.
+        let mut pending = Vec::with_capacity(self.width() * self.height());
+        self.set_led_adjusted(x as usize, y as usize, color);
+        pending.push((x as usize, y as usize));
+
+        while let Some((seed_x, y)) = pending.pop() {
+            let mut left = seed_x;
+            while left > 0 && self.get_led_adjusted(left - 1, y) == target_color {
+                left -= 1;
+                self.set_led_adjusted(left, y, color);
+            }
+
+            let mut right = seed_x;
+            while right + 1 < self.width() && self.get_led_adjusted(right + 1, y) == target_color {
+                right += 1;
+                self.set_led_adjusted(right, y, color);
+            }
+
+            for next_y in [y.wrapping_sub(1), y + 1] {
+                if next_y >= self.height() {
+                    continue;
+                }
+
+                // one seed for every run of the target color that touches the span
+                let mut in_run = false;
+                for x in left..=right {
+                    let is_target = self.get_led_adjusted(x, next_y) == target_color;
+                    if is_target && !in_run {
+                        self.set_led_adjusted(x, next_y, color);
+                        pending.push((x, next_y));
+                    }
+                    in_run = is_target;
+                }
+            }
+        }
+    }
+
+    fn in_bounds(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height()
+    }
+
+    fn plot(&mut self, x: i64, y: i64, color: AdjustedColor) {
+        if self.in_bounds(x, y) {
+            self.set_led_adjusted(x as usize, y as usize, color);
+        }
+    }
+
+    fn horizontal_span(&mut self, x0: i64, x1: i64, y: i64, color: AdjustedColor) {
+        if y < 0 || y >= self.height() as i64 {
+            return;
+        }
+
+        for x in x0.max(0)..=x1.min(self.width() as i64 - 1) {
+            self.set_led_adjusted(x as usize, y as usize, color);
+        }
+    }
+
+    // Bresenham's line algorithm, which works in every octant. Every pixel is worked out from how
+    // far along the major axis it is, so the line can be clipped to the buffer first, and lines
+    // that reach far off of it only cost as much as the part that's on it.
+    fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: AdjustedColor) {
+        let (x0, y0, x1, y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+        let (delta_x, delta_y) = (x1 - x0, y1 - y0);
+
+        if delta_x.abs() >= delta_y.abs() {
+            for (x, y) in line_pixels(x0, y0, delta_x, delta_y, self.width()) {
+                self.plot(x, y, color);
+            }
+        } else {
+            for (y, x) in line_pixels(y0, x0, delta_y, delta_x, self.height()) {
+                self.plot(x, y, color);
+            }
+        }
+    }
+
+    fn fill_ellipse_rows(
+        &mut self,
+        center_x: i64,
+        center_y: i64,
+        radius_x: i32,
+        radius_y: i32,
+        color: AdjustedColor,
+    ) {
+        let quadrant = EllipseQuadrant::new(radius_x, radius_y);
+
+        for y in visible_offsets(center_y, self.height(), radius_y as i64) {
+            let half_width = match &quadrant {
+                Some(quadrant) => quadrant.half_width(y),
+                None => radius_x as i64,
+            };
+
+            for y in [y, -y] {
+                self.horizontal_span(
+                    center_x - half_width,
+                    center_x + half_width,
+                    center_y + y,
+                    color,
+                );
+            }
+        }
+    }
+}
+
+// The pixels of a line along its major axis, for the steps where the major coordinate is within
+// 0..major_size. The minor coordinate is rounded the same way Bresenham's error term does it.
+fn line_pixels(
+    major_start: i64,
+    minor_start: i64,
+    delta_major: i64,
+    delta_minor: i64,
+    major_size: usize,
+) -> impl Iterator<Item = (i64, i64)> {
+    let length = delta_major.abs();
+    let major_step = if delta_major < 0 { -1 } else { 1 };
+    let minor_step = if delta_minor < 0 { -1 } else { 1 };
+
+    // the steps where the major coordinate lands in 0..major_size
+    let last_major = major_size as i64 - 1;
+    let (first_step, last_step) = if major_step > 0 {
+        (-major_start, last_major - major_start)
+    } else {
+        (major_start - last_major, major_start)
+    };
+
+    (first_step.max(0)..=last_step.min(length)).map(move |step| {
+        let minor_offset = (2 * step as i128 * delta_minor.abs() as i128 + length as i128)
+            / (2 * length.max(1) as i128);
+
+        (
+            major_start + step * major_step,
+            minor_start + minor_offset as i64 * minor_step,
+        )
+    })
+}
+
+// The offsets from a center, up to the given limit, that land in 0..size on either side of it. The
+// two ranges are merged when they overlap, so no offset comes up twice.
+fn visible_offsets(center: i64, size: usize, limit: i64) -> impl Iterator<Item = i64> {
+    let last = size as i64 - 1;
+    let after = (-center).max(0)..=(last - center).min(limit);
+    let before = (center - last).max(0)..=center.min(limit);
+
+    let (first, second) = if after.start() <= before.start() {
+        (after, before)
+    } else {
+        (before, after)
+    };
+
+    let (first, second) = if second.start() <= &(first.end() + 1) {
+        (*first.start()..=*first.end().max(second.end()), None)
+    } else {
+        (first, Some(second))
+    };
+
+    first.chain(second.into_iter().flatten())
+}
+
+// One quadrant of a midpoint ellipse, with every pixel worked out on its own so any part of it can
+// be drawn without walking the rest. The shallow part goes from the top towards the right, with a
+// pixel per column, and the steep part goes from there down to the right end, with a run of pixels
+// per row. Flat ellipses aren't covered, since they're only a line.
+struct EllipseQuadrant {
+    radius_x_squared: u128,
+    radius_y_squared: u128,
+    // the first column past the shallow part
+    shallow_end: i64,
+    // the first row above the steep part
+    steep_end: i64,
+}
+
+impl EllipseQuadrant {
+    fn new(radius_x: i32, radius_y: i32) -> Option<Self> {
+        if radius_x <= 0 || radius_y <= 0 {
+            return None;
+        }
+
+        let mut quadrant = Self {
+            radius_x_squared: (radius_x as u128).pow(2),
+            radius_y_squared: (radius_y as u128).pow(2),
+            shallow_end: 0,
+            steep_end: 0,
+        };
+
+        // the slope only gets steeper towards the right, so the end of the shallow part can be
+        // searched for
+        let (mut low, mut high) = (0, radius_x as i64 + 1);
+        while low < high {
+            let x = (low + high) / 2;
+            if quadrant.radius_y_squared * x as u128
+                <= quadrant.radius_x_squared * quadrant.shallow_y(x) as u128
+            {
+                low = x + 1;
+            } else {
+                high = x;
+            }
+        }
+        quadrant.shallow_end = low;
+        quadrant.steep_end = quadrant.shallow_y(low - 1);
+
+        Some(quadrant)
+    }
+
+    // the row of the pixel in a column of the shallow part, which is the last one whose bottom edge
+    // is still inside
+    fn shallow_y(&self, x: i64) -> i64 {
+        let x_squared = (x as u128).pow(2);
+        let limit =
+            4 * self.radius_y_squared * (self.radius_x_squared - x_squared) / self.radius_x_squared;
+        ((1 + isqrt(limit)) / 2) as i64
+    }
+
+    // the column of the outermost pixel in a row of the steep part, which is the last one whose
+    // left edge is still inside
+    fn steep_x(&self, y: i64) -> i64 {
+        let y_squared = (y as u128).pow(2);
+        let limit =
+            4 * self.radius_x_squared * (self.radius_y_squared - y_squared) / self.radius_y_squared;
+        ((1 + isqrt(limit)) / 2) as i64
+    }
+
+    // the pixels of a row in the steep part, which reach back to the pixel above so there are no
+    // gaps
+    fn steep_run(&self, y: i64) -> (i64, i64) {
+        let above_x = if y + 1 < self.steep_end {
+            self.steep_x(y + 1)
+        } else {
+            self.shallow_end - 1
+        };
+
+        let x = self.steep_x(y);
+        ((above_x + 1).min(x), x)
+    }
+
+    // the outermost pixel of the outline in any row
+    fn half_width(&self, y: i64) -> i64 {
+        if y < self.steep_end {
+            return self.steep_x(y);
+        }
+        if y == 0 {
+            return self.shallow_end - 1;
+        }
+
+        // the last column of the shallow part that reaches down to the row
+        let edge_squared = (2 * y as u128 - 1).pow(2);
+        let limit = (4 * self.radius_x_squared * self.radius_y_squared
+            - self.radius_x_squared * edge_squared)
+            / (4 * self.radius_y_squared);
+        (isqrt(limit) as i64).min(self.shallow_end - 1)
+    }
+}
+
+/// The largest number whose square doesn't exceed the value.
+pub(crate) fn isqrt(value: u128) -> u128 {
+    let mut result = 0;
+
+    let mut bit = 1 << 63;
+    while bit > 0 {
+        let candidate = result | bit;
+        if candidate * candidate <= value {
+            result = candidate;
+        }
+        bit >>= 1;
+    }
+
+    result
+}
+
+// even-odd test of a pixel against every edge, with the points on the pixel centers like everywhere
+// else
+fn contains_point(points: &[(i32, i32)], x: i32, y: i32) -> bool {
+    let (x, y) = (x as i64, y as i64);
+    let mut inside = false;
+
+    for (i, &(x0, y0)) in points.iter().enumerate() {
+        let (x1, y1) = points[(i + 1) % points.len()];
+        let (x0, y0, x1, y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+
+        if (y0 > y) != (y1 > y) {
+            // the x coordinate where the edge crosses the row, compared without dividing
+            let crossing_offset = (y - y0) * (x1 - x0);
+            let pixel_offset = (x - x0) * (y1 - y0);
+
+            if (y1 > y0 && pixel_offset < crossing_offset)
+                || (y1 < y0 && pixel_offset > crossing_offset)
+            {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{PANEL_HEIGHT, PANEL_WIDTH};
+
+    const ON: AdjustedColor = AdjustedColor {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    fn lit_pixels(buffer: &BackBuffer) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..PANEL_HEIGHT {
+            for x in 0..PANEL_WIDTH {
+                if buffer.get_led_adjusted(x, y) == ON {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    // the plain version, which walks every pixel of the line
+    fn bresenham(buffer: &mut BackBuffer, mut x0: i64, mut y0: i64, x1: i64, y1: i64) {
+        let delta_x = (x1 - x0).abs();
+        let delta_y = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mut error = delta_x + delta_y;
+
+        loop {
+            buffer.plot(x0, y0, ON);
+
+            if x0 == x1 && y0 == y1 {
+                break;
+            }
+
+            let doubled_error = error * 2;
+            if doubled_error >= delta_y {
+                error += delta_y;
+                x0 += step_x;
+            }
+            if doubled_error <= delta_x {
+                error += delta_x;
+                y0 += step_y;
+            }
+        }
+    }
+
+    // the plain version, which walks a whole octant
+    fn midpoint_circle(buffer: &mut BackBuffer, center_x: i64, center_y: i64, radius: i64) {
+        let mut x = radius;
+        let mut y = 0;
+        let mut error = 1 - radius;
+
+        while x >= y {
+            for (offset_x, offset_y) in [(x, y), (y, x)] {
+                buffer.plot(center_x + offset_x, center_y + offset_y, ON);
+                buffer.plot(center_x - offset_x, center_y + offset_y, ON);
+                buffer.plot(center_x + offset_x, center_y - offset_y, ON);
+                buffer.plot(center_x - offset_x, center_y - offset_y, ON);
+            }
+
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    #[test]
+    fn clipped_lines_match_bresenham() {
+        let points = [
+            (-20, -5),
+            (-3, 4),
+            (0, 0),
+            (5, 2),
+            (11, 7),
+            (30, -9),
+            (6, 40),
+        ];
+
+        for &(x0, y0) in &points {
+            for &(x1, y1) in &points {
+                let mut expected: BackBuffer = BackBuffer::default();
+                bresenham(&mut expected, x0, y0, x1, y1);
+                let mut drawn: BackBuffer = BackBuffer::default();
+                drawn.draw_line(x0 as i32, y0 as i32, x1 as i32, y1 as i32, ON);
+
+                assert_eq!(lit_pixels(&drawn), lit_pixels(&expected));
+            }
+        }
+    }
+
+    #[test]
+    fn circles_match_midpoint() {
+        for radius in 0..20 {
+            for (center_x, center_y) in [(5, 3), (-4, 2), (15, 10)] {
+                let mut expected: BackBuffer = BackBuffer::default();
+                midpoint_circle(&mut expected, center_x, center_y, radius);
+                let mut drawn: BackBuffer = BackBuffer::default();
+                drawn.draw_circle(center_x as i32, center_y as i32, radius as i32, ON);
+
+                assert_eq!(lit_pixels(&drawn), lit_pixels(&expected));
+            }
+        }
+    }
+
+    #[test]
+    fn filled_ellipses_reach_their_outline() {
+        // only ellipses that fit, so no part of the outline gets clipped
+        for radius_x in 0..=5 {
+            for radius_y in 0..=3 {
+                let mut outline: BackBuffer = BackBuffer::default();
+                outline.draw_ellipse(5, 3, radius_x, radius_y, ON);
+                let mut filled: BackBuffer = BackBuffer::default();
+                filled.fill_ellipse(5, 3, radius_x, radius_y, ON);
+
+                // every row spans from the leftmost to the rightmost pixel of the outline
+                for y in 0..PANEL_HEIGHT {
+                    let row: Vec<usize> = (0..PANEL_WIDTH)
+                        .filter(|&x| outline.get_led_adjusted(x, y) == ON)
+                        .collect();
+                    for x in 0..PANEL_WIDTH {
+                        let inside = row.first().is_some_and(|&left| left <= x)
+                            && row.last().is_some_and(|&right| x <= right);
+                        assert_eq!(filled.get_led_adjusted(x, y) == ON, inside);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn huge_shapes_only_walk_the_buffer() {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        buffer.draw_line(0, 0, i32::MAX, 0, ON);
+        buffer.draw_line(i32::MIN, i32::MIN, i32::MAX, i32::MAX, ON);
+        buffer.draw_circle(i32::MIN, 0, i32::MAX, ON);
+        buffer.fill_ellipse(0, 0, i32::MAX, i32::MAX, ON);
+        buffer.draw_rect(i32::MIN, i32::MIN, i32::MAX, i32::MAX, ON);
+        buffer.fill_rect(i32::MAX, i32::MAX, i32::MAX, i32::MAX, ON);
+
+        assert_eq!(lit_pixels(&buffer).len(), PANEL_WIDTH * PANEL_HEIGHT);
+    }
+
+    #[test]
+    fn flood_fill_stays_inside_the_outline() {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        buffer.draw_rect(2, 1, 6, 5, ON);
+        buffer.flood_fill(4, 3, ON);
+
+        assert_eq!(lit_pixels(&buffer).len(), 6 * 5);
+    }
+}
@@ -0,0 +1,245 @@
+mod font_3x5;
+mod font_5x7;
+
+pub use font_3x5::FONT_3X5;
+pub use font_5x7::FONT_5X7;
+
+use crate::color::AdjustedColor;
+use crate::framebuffer::BackBuffer;
+
+// Fonts cover printable ASCII, and every glyph is stored as a list of columns, with the top row in
+// the lowest bit. Glyphs are proportional: the empty columns on either side get trimmed when the
+// font is built, so a narrow character like 'i' doesn't take up as much space as a 'W'.
+
+const FIRST_CHAR: char = ' ';
+const LAST_CHAR: char = '~';
+pub const GLYPH_COUNT: usize = LAST_CHAR as usize - FIRST_CHAR as usize + 1;
+
+const MAX_GLYPH_WIDTH: usize = 8;
+const MAX_GLYPH_HEIGHT: usize = u8::BITS as usize;
+
+// drawn for every character that the font doesn't have
+const FALLBACK_CHAR: char = '?';
+
+#[derive(Copy, Clone)]
+pub struct Glyph {
+    columns: [u8; MAX_GLYPH_WIDTH],
+    width: u8,
+}
+
+impl Glyph {
+    const EMPTY: Self = Self {
+        columns: [0; MAX_GLYPH_WIDTH],
+        width: 0,
+    };
+
+    pub fn width(&self) -> i32 {
+        self.width as i32
+    }
+
+    /// Whether the pixel at the given position inside the glyph is lit.
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        x < self.width as usize && (self.columns[x] >> y) & 0b1 != 0
+    }
+}
+
+/// Changes the gap between two specific characters that follow each other, on top of the letter
+/// spacing. Negative adjustments pull the characters closer together.
+#[derive(Copy, Clone)]
+pub struct KerningPair {
+    pub left: char,
+    pub right: char,
+    pub adjustment: i8,
+}
+
+pub struct Font {
+    glyphs: [Glyph; GLYPH_COUNT],
+    height: u8,
+    letter_spacing: u8,
+    kerning: &'static [KerningPair],
+}
+
+impl Font {
+    /// Builds a proportional font out of fixed width glyph columns, starting at the space
+    /// character. Glyphs without any lit pixels, like the space, are given the space width.
+    pub const fn new<const GLYPH_WIDTH: usize>(
+        columns: &[[u8; GLYPH_WIDTH]; GLYPH_COUNT],
+        height: u8,
+        space_width: u8,
+        letter_spacing: u8,
+        kerning: &'static [KerningPair],
+    ) -> Self {
+        assert!(
+            GLYPH_WIDTH <= MAX_GLYPH_WIDTH,
+            "the glyphs are too wide for the font"
+        );
+        assert!(
+            height as usize <= MAX_GLYPH_HEIGHT,
+            "the glyphs are too tall for the font"
+        );
+
+        let mut glyphs = [Glyph::EMPTY; GLYPH_COUNT];
+
+        let mut i = 0;
+        while i < GLYPH_COUNT {
+            let glyph_columns = &columns[i];
+
+            let mut start = 0;
+            while start < GLYPH_WIDTH && glyph_columns[start] == 0 {
+                start += 1;
+            }
+            let mut end = GLYPH_WIDTH;
+            while end > start && glyph_columns[end - 1] == 0 {
+                end -= 1;
+            }
+
+            if start == end {
+                glyphs[i].width = space_width;
+            } else {
+                let mut x = start;
+                while x < end {
+                    glyphs[i].columns[x - start] = glyph_columns[x];
+                    x += 1;
+                }
+                glyphs[i].width = (end - start) as u8;
+            }
+
+            i += 1;
+        }
+
+        Self {
+            glyphs,
+            height,
+            letter_spacing,
+            kerning,
+        }
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height as i32
+    }
+
+    pub fn glyph(&self, character: char) -> &Glyph {
+        let character = if (FIRST_CHAR..=LAST_CHAR).contains(&character) {
+            character
+        } else {
+            FALLBACK_CHAR
+        };
+
+        &self.glyphs[character as usize - FIRST_CHAR as usize]
+    }
+
+    /// The gap in pixels between two characters that follow each other.
+    pub fn spacing(&self, left: char, right: char) -> i32 {
+        let adjustment = self
+            .kerning
+            .iter()
+            .find(|pair| pair.left == left && pair.right == right)
+            .map_or(0, |pair| pair.adjustment as i32);
+
+        self.letter_spacing as i32 + adjustment
+    }
+
+    /// The width in pixels that the text takes up when drawn, without drawing it.
+    pub fn text_width(&self, text: &str) -> i32 {
+        self.layout(text, |_, _| {})
+    }
+
+    // calls the function with every glyph and its x offset, and returns the total width
+    fn layout(&self, text: &str, mut place_glyph: impl FnMut(&Glyph, i32)) -> i32 {
+        let mut cursor = 0;
+        let mut previous = None;
+
+        for character in text.chars() {
+            if let Some(previous) = previous {
+                cursor += self.spacing(previous, character);
+            }
+
+            let glyph = self.glyph(character);
+            place_glyph(glyph, cursor);
+            cursor += glyph.width();
+            previous = Some(character);
+        }
+
+        cursor
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> BackBuffer<WIDTH, HEIGHT> {
+    /// Draws a single line of text with its top left corner at the given position, clipped to the
+    /// panel. Returns the width of the text in pixels, including the parts that got clipped, so it
+    /// can be used for centering or scrolling.
+    pub fn draw_text(
+        &mut self,
+        text: &str,
+        x: i32,
+        y: i32,
+        font: &Font,
+        color: impl Into<AdjustedColor>,
+    ) -> i32 {
+        let color = color.into();
+        let panel_width = self.width() as i32;
+
+        font.layout(text, |glyph, offset| {
+            let glyph_x = x + offset;
+
+            // skip the glyphs that are entirely off the panel
+            if glyph_x >= panel_width || glyph_x + glyph.width() <= 0 {
+                return;
+            }
+
+            for glyph_y in 0..font.height as usize {
+                for column in 0..glyph.width as usize {
+                    if glyph.is_set(column, glyph_y) {
+                        self.draw_pixel(glyph_x + column as i32, y + glyph_y as i32, color);
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::WHITE;
+
+    #[test]
+    fn kerned_pairs_are_pulled_together() {
+        let font = &FONT_3X5;
+        let text = "LT.";
+
+        // 'L' + 'T' and 'T' + '.' both drop the letter spacing
+        assert_eq!(font.spacing('L', 'T'), 0);
+        assert_eq!(font.spacing('T', '.'), 0);
+        assert_eq!(font.spacing('.', 'L'), 1);
+
+        let glyphs = ['L', 'T', '.'].map(|character| font.glyph(character));
+        let offsets = [0, 3, 6];
+        assert_eq!(glyphs.map(Glyph::width), [3, 3, 1]);
+        assert_eq!(font.text_width(text), 7);
+
+        let mut buffer: BackBuffer = BackBuffer::default();
+        let width = buffer.draw_text(text, 0, 0, font, WHITE);
+        assert_eq!(width, 7);
+
+        // every column belongs to exactly one glyph, so the glyphs must touch without overlapping
+        let lit = WHITE.adjust_for_led();
+        for x in 0..buffer.width() {
+            let glyph = glyphs
+                .iter()
+                .zip(offsets)
+                .find(|(glyph, offset)| (*offset..offset + glyph.width()).contains(&(x as i32)));
+
+            for y in 0..buffer.height() {
+                let expected =
+                    glyph.is_some_and(|(glyph, offset)| glyph.is_set(x - offset as usize, y));
+                assert_eq!(
+                    buffer.get_led_adjusted(x, y) == lit,
+                    expected,
+                    "({x}, {y}) is drawn wrong"
+                );
+            }
+        }
+    }
+}
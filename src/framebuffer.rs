@@ -1,4 +1,19 @@
-use crate::color::{AdjustedColor, Color};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+#[cfg(target_arch = "arm")]
+use cortex_m::asm::wfi;
+
+use crate::color::{AdjustedColor, AdjustedColorRgba, BlendMode, Color};
+use crate::intrinsics::BATCH_SIZE;
+use crate::pins::LED_OUTPUT_PIN_INDICES;
+
+// The size of the panel that the firmware is built for. Everything that isn't tied to the refresh
+// interrupt can be used with other sizes as well.
+pub const PANEL_WIDTH: usize = 12;
+pub const PANEL_HEIGHT: usize = 8;
+
+pub const FRAME_PERIOD_FRACTION_BITS: u32 = 12;
 
 #[repr(u8)]
 pub enum ColorLines {
@@ -12,43 +27,293 @@ impl ColorLines {
     pub const COUNT: usize = Self::VALUES.len();
 }
 
-#[derive(Default)]
-pub struct Framebuffer {
-    pub(crate) front_buffer: FrontBuffer,
-    pub(crate) back_buffer: BackBuffer,
+// Every row has a line per color, and the lines are shifted out in that order. Flattening the
+// outer two levels gives the shift lines.
+pub type BitLines<const WIDTH: usize, const HEIGHT: usize> =
+    [[[u8; WIDTH]; ColorLines::COUNT]; HEIGHT];
+
+/// A quarter turn of the image, clockwise.
+#[derive(Copy, Clone, Eq, PartialEq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+/// How the image is turned to match the way the panel is mounted. Programs draw in logical
+/// coordinates, which get mapped onto the LEDs when the back buffer is addressed.
+#[derive(Copy, Clone, Eq, PartialEq, Default)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    /// Mirrors the image left to right, before it gets rotated.
+    pub mirror: bool,
+}
+
+impl Orientation {
+    pub const NATIVE: Orientation = Orientation {
+        rotation: Rotation::None,
+        mirror: false,
+    };
+
+    /// Whether the logical width is the panel height, and the other way around.
+    pub const fn swaps_axes(&self) -> bool {
+        matches!(
+            self.rotation,
+            Rotation::Clockwise90 | Rotation::Clockwise270
+        )
+    }
+}
+
+const fn empty_bit_lines<const WIDTH: usize, const HEIGHT: usize>() -> BitLines<WIDTH, HEIGHT> {
+    [[[0; WIDTH]; ColorLines::COUNT]; HEIGHT]
+}
+
+// The front buffer is owned by the shift engine, which runs in the refresh interrupt. The only way
+// for a frame to get there is through the handoff.
+pub struct Framebuffer<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    pub(crate) back_buffer: BackBuffer<WIDTH, HEIGHT>,
+    handoff: &'static FrameHandoff<WIDTH, HEIGHT>,
+    brightness: u8,
 }
 
-#[derive(Default)]
 #[repr(align(4))] // align to batch size
-pub struct FrontBuffer {
-    pub(crate) bit_target_lines:
-        [[u8; Framebuffer::WIDTH]; Framebuffer::HEIGHT * ColorLines::COUNT],
-    pub(crate) bit_current_lines:
-        [[u8; Framebuffer::WIDTH]; Framebuffer::HEIGHT * ColorLines::COUNT],
+pub struct FrontBuffer<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    pub(crate) bit_target_lines: BitLines<WIDTH, HEIGHT>,
+    pub(crate) bit_current_lines: BitLines<WIDTH, HEIGHT>,
 }
 
-#[derive(Default)]
 #[repr(align(4))] // align to batch size
-pub struct BackBuffer {
-    pub(crate) bit_lines: [[u8; Framebuffer::WIDTH]; Framebuffer::HEIGHT * ColorLines::COUNT],
+pub struct BackBuffer<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    pub(crate) bit_lines: BitLines<WIDTH, HEIGHT>,
+    orientation: Orientation,
 }
 
-impl Framebuffer {
-    pub const WIDTH: usize = 12;
-    pub const HEIGHT: usize = 8;
+/// Makes the compiler evaluate a compile time check, like [`Framebuffer::GEOMETRY_CHECK`]. Checks
+/// in associated constants only fail the build once they are referenced from code that gets built.
+#[allow(clippy::let_unit_value)]
+pub const fn build_check(check: ()) {
+    let () = check;
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Framebuffer<WIDTH, HEIGHT> {
+    // Referenced by everything that holds a frame, so a panel size that the pins can't drive fails
+    // to build instead of misbehaving.
+    pub(crate) const GEOMETRY_CHECK: () = {
+        assert!(WIDTH > 0 && HEIGHT > 0, "the panel can't be empty");
+        assert!(
+            WIDTH % BATCH_SIZE == 0,
+            "the panel width has to be a multiple of BATCH_SIZE"
+        );
+        assert!(
+            WIDTH <= LED_OUTPUT_PIN_INDICES.len(),
+            "the panel is wider than the amount of GPIO6 output pins"
+        );
+    };
+
+    pub fn new(handoff: &'static FrameHandoff<WIDTH, HEIGHT>) -> Self {
+        Self {
+            back_buffer: BackBuffer::new(),
+            handoff,
+            brightness: u8::MAX,
+        }
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
 
+    /// Sets the brightness that every flipped frame gets scaled by, where [`u8::MAX`] leaves the
+    /// frames untouched.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    /// Hands a copy of the back buffer to the shift engine, which will show it at the start of the
+    /// next frame. Only waits if the previously flipped frame hasn't been picked up yet.
     pub fn flip(&mut self) {
-        self.front_buffer.bit_target_lines = self.back_buffer.bit_lines;
+        let brightness = self.brightness;
+
+        if brightness == u8::MAX {
+            self.handoff
+                .submit_with(|target| *target = self.back_buffer.bit_lines);
+        } else {
+            self.handoff.submit_with(|target| {
+                scale_bit_lines(&self.back_buffer.bit_lines, target, brightness)
+            });
+        }
+    }
+
+    /// Waits until the shift engine has picked up the last flipped frame.
+    pub fn wait_for_vsync(&self) {
+        self.handoff.wait_until_taken();
     }
 }
 
-impl BackBuffer {
+/// A single frame slot shared between the render code and the refresh interrupt. There must only
+/// be one side submitting frames and one side taking them.
+pub struct FrameHandoff<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    bit_lines: UnsafeCell<BitLines<WIDTH, HEIGHT>>,
+    full: AtomicBool,
+    rtc_mask: AtomicU32,
+    frame_period: AtomicU32,
+}
+
+// SAFETY: the bit lines are only written by the submitting side while the slot is empty, and only
+// read by the taking side while the slot is full. The flag is released after every access.
+unsafe impl<const WIDTH: usize, const HEIGHT: usize> Sync for FrameHandoff<WIDTH, HEIGHT> {}
+
+impl<const WIDTH: usize, const HEIGHT: usize> FrameHandoff<WIDTH, HEIGHT> {
+    pub const fn new(rtc_mask: u32) -> Self {
+        build_check(Framebuffer::<WIDTH, HEIGHT>::GEOMETRY_CHECK);
+
+        Self {
+            bit_lines: UnsafeCell::new(empty_bit_lines()),
+            full: AtomicBool::new(false),
+            rtc_mask: AtomicU32::new(rtc_mask),
+            frame_period: AtomicU32::new(0),
+        }
+    }
+
+    /// Waits for the slot to be empty, and lets the given function write the next frame into it.
+    pub fn submit_with<F: FnOnce(&mut BitLines<WIDTH, HEIGHT>)>(&self, fill: F) {
+        self.wait_until_taken();
+
+        unsafe {
+            fill(&mut *self.bit_lines.get());
+        }
+        self.full.store(true, Ordering::Release);
+    }
+
+    pub fn wait_until_taken(&self) {
+        while self.full.load(Ordering::Acquire) {
+            // the refresh interrupt wakes us back up
+            #[cfg(target_arch = "arm")]
+            wfi();
+            #[cfg(not(target_arch = "arm"))]
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Whether a submitted frame is waiting to be taken.
+    pub fn is_full(&self) -> bool {
+        self.full.load(Ordering::Acquire)
+    }
+
+    /// Copies the submitted frame into the target, if there is one.
+    pub fn take(&self, target: &mut BitLines<WIDTH, HEIGHT>) -> bool {
+        if !self.full.load(Ordering::Acquire) {
+            return false;
+        }
+
+        *target = unsafe { *self.bit_lines.get() };
+        self.full.store(false, Ordering::Release);
+
+        true
+    }
+
+    pub fn rtc_mask(&self) -> u32 {
+        self.rtc_mask.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rtc_mask(&self, rtc_mask: u32) {
+        self.rtc_mask.store(rtc_mask, Ordering::Relaxed);
+    }
+
+    /// The time between frames in RTC ticks, with [`FRAME_PERIOD_FRACTION_BITS`] fractional bits.
+    /// Zero means that frames are paced by the RTC mask instead.
+    pub fn frame_period(&self) -> u32 {
+        self.frame_period.load(Ordering::Relaxed)
+    }
+
+    pub fn set_frame_period(&self, frame_period: u32) {
+        self.frame_period.store(frame_period, Ordering::Relaxed);
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> FrontBuffer<WIDTH, HEIGHT> {
+    pub const fn new() -> Self {
+        build_check(Framebuffer::<WIDTH, HEIGHT>::GEOMETRY_CHECK);
+
+        Self {
+            bit_target_lines: empty_bit_lines(),
+            bit_current_lines: empty_bit_lines(),
+        }
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Default for FrontBuffer<WIDTH, HEIGHT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> BackBuffer<WIDTH, HEIGHT> {
+    pub const fn new() -> Self {
+        build_check(Framebuffer::<WIDTH, HEIGHT>::GEOMETRY_CHECK);
+
+        Self {
+            bit_lines: empty_bit_lines(),
+            orientation: Orientation::NATIVE,
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Changes how coordinates map onto the LEDs. Whatever has already been drawn stays on the same
+    /// LEDs, so the buffer should be redrawn afterwards.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    /// The width that programs draw in, which is the panel height when the image is turned by a
+    /// quarter.
+    pub fn width(&self) -> usize {
+        if self.orientation.swaps_axes() {
+            HEIGHT
+        } else {
+            WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.orientation.swaps_axes() {
+            WIDTH
+        } else {
+            HEIGHT
+        }
+    }
+
+    /// Turns every LED off, and keeps the orientation.
+    pub fn clear(&mut self) {
+        self.bit_lines = empty_bit_lines();
+    }
+
+    // maps logical coordinates to the LED that they end up on
+    fn led_position(&self, x: usize, y: usize) -> (usize, usize) {
+        let x = if self.orientation.mirror {
+            self.width() - 1 - x
+        } else {
+            x
+        };
+
+        match self.orientation.rotation {
+            Rotation::None => (x, y),
+            Rotation::Clockwise90 => (WIDTH - 1 - y, x),
+            Rotation::Clockwise180 => (WIDTH - 1 - x, HEIGHT - 1 - y),
+            Rotation::Clockwise270 => (y, HEIGHT - 1 - x),
+        }
+    }
+
     pub fn try_set_led(&mut self, led_x: usize, led_y: usize, color: Color) {
         self.try_set_led_adjusted(led_x, led_y, color.adjust_for_led())
     }
 
     pub fn try_set_led_adjusted(&mut self, led_x: usize, led_y: usize, color: AdjustedColor) {
-        if led_x < Framebuffer::WIDTH && led_y < Framebuffer::HEIGHT {
+        if led_x < self.width() && led_y < self.height() {
             self.set_led_adjusted(led_x, led_y, color);
         }
     }
@@ -58,23 +323,113 @@ impl BackBuffer {
     }
 
     pub fn set_led_adjusted(&mut self, led_x: usize, led_y: usize, color: AdjustedColor) {
-        debug_assert!(led_x < Framebuffer::WIDTH);
-        debug_assert!(led_y < Framebuffer::HEIGHT);
+        debug_assert!(led_x < self.width());
+        debug_assert!(led_y < self.height());
 
+        let (led_x, led_y) = self.led_position(led_x, led_y);
         unsafe {
-            let led_start_column = led_y * ColorLines::COUNT;
-            *(self
-                .bit_lines
-                .get_unchecked_mut(led_start_column + (ColorLines::Red as usize))
+            let led_lines = self.bit_lines.get_unchecked_mut(led_y);
+            *(led_lines
+                .get_unchecked_mut(ColorLines::Red as usize)
                 .get_unchecked_mut(led_x)) = color.r;
-            *(self
-                .bit_lines
-                .get_unchecked_mut(led_start_column + (ColorLines::Green as usize))
+            *(led_lines
+                .get_unchecked_mut(ColorLines::Green as usize)
                 .get_unchecked_mut(led_x)) = color.g;
-            *(self
-                .bit_lines
-                .get_unchecked_mut(led_start_column + (ColorLines::Blue as usize))
+            *(led_lines
+                .get_unchecked_mut(ColorLines::Blue as usize)
                 .get_unchecked_mut(led_x)) = color.b;
         }
     }
+
+    /// Blends the color over the LED that is already there.
+    pub fn blend_led(
+        &mut self,
+        led_x: usize,
+        led_y: usize,
+        color: impl Into<AdjustedColorRgba>,
+        blend_mode: BlendMode,
+    ) {
+        let blended = blend_mode.blend(self.get_led_adjusted(led_x, led_y), color.into());
+        self.set_led_adjusted(led_x, led_y, blended);
+    }
+
+    pub fn try_blend_led(
+        &mut self,
+        led_x: usize,
+        led_y: usize,
+        color: impl Into<AdjustedColorRgba>,
+        blend_mode: BlendMode,
+    ) {
+        if led_x < self.width() && led_y < self.height() {
+            self.blend_led(led_x, led_y, color, blend_mode);
+        }
+    }
+
+    pub fn try_get_led(&self, led_x: usize, led_y: usize) -> Option<Color> {
+        self.try_get_led_adjusted(led_x, led_y)
+            .map(AdjustedColor::to_color)
+    }
+
+    /// Reads back the color of an LED. The adjustment for the LEDs can't be reversed exactly, so
+    /// this is only an approximation of the color that was set, see [`AdjustedColor::to_color`].
+    pub fn get_led(&self, led_x: usize, led_y: usize) -> Color {
+        self.get_led_adjusted(led_x, led_y).to_color()
+    }
+
+    pub fn try_get_led_adjusted(&self, led_x: usize, led_y: usize) -> Option<AdjustedColor> {
+        if led_x < self.width() && led_y < self.height() {
+            Some(self.get_led_adjusted(led_x, led_y))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_led_adjusted(&self, led_x: usize, led_y: usize) -> AdjustedColor {
+        let (led_x, led_y) = self.led_position(led_x, led_y);
+        let led_lines = &self.bit_lines[led_y];
+
+        AdjustedColor {
+            r: led_lines[ColorLines::Red as usize][led_x],
+            g: led_lines[ColorLines::Green as usize][led_x],
+            b: led_lines[ColorLines::Blue as usize][led_x],
+        }
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Default for BackBuffer<WIDTH, HEIGHT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Scales every pixel by the same factor so the hue stays the same. A pixel that was lit can't end
+// up completely dark unless the brightness is zero, so its brightest channel is kept at 1 at least.
+fn scale_bit_lines<const WIDTH: usize, const HEIGHT: usize>(
+    source: &BitLines<WIDTH, HEIGHT>,
+    target: &mut BitLines<WIDTH, HEIGHT>,
+    brightness: u8,
+) {
+    for (source_lines, target_lines) in source.iter().zip(target.iter_mut()) {
+        for x in 0..WIDTH {
+            let mut brightest_channel = 0;
+            let mut any_lit = false;
+
+            for channel in 0..ColorLines::COUNT {
+                let value = source_lines[channel][x];
+                // rounded to the nearest value
+                target_lines[channel][x] = ((value as u16 * brightness as u16
+                    + (u8::MAX as u16 / 2))
+                    / u8::MAX as u16) as u8;
+
+                if value > source_lines[brightest_channel][x] {
+                    brightest_channel = channel;
+                }
+                any_lit |= target_lines[channel][x] != 0;
+            }
+
+            if !any_lit && brightness != 0 && source_lines[brightest_channel][x] != 0 {
+                target_lines[brightest_channel][x] = 1;
+            }
+        }
+    }
 }
@@ -1,13 +1,20 @@
+#[cfg(target_arch = "arm")]
 use core::arch::asm;
+#[cfg(target_arch = "arm")]
 use core::mem::MaybeUninit;
+#[cfg(target_arch = "arm")]
 use core::ptr::addr_of_mut;
 
+#[cfg(target_arch = "arm")]
 use cortex_m::register::apsr;
+#[cfg(target_arch = "arm")]
 use embedded_alloc::Heap;
-use teensy4_bsp::board::ARM_FREQUENCY;
 
+use crate::led_driver::ARM_FREQUENCY;
+#[cfg(target_arch = "arm")]
 use crate::peripherals;
 
+#[cfg(target_arch = "arm")]
 extern "unadjusted" {
     #[link_name = "llvm.arm.uadd8"]
     fn arm_uadd8(a: u32, b: u32) -> u32;
@@ -15,6 +22,7 @@ extern "unadjusted" {
 
 pub const BATCH_SIZE: usize = 4;
 
+#[cfg(target_arch = "arm")]
 pub fn pwm_pulse_batched(
     current_values: &mut [u8; BATCH_SIZE],
     target_values: &[u8; BATCH_SIZE],
@@ -36,10 +44,44 @@ pub fn pwm_pulse_batched(
     *out_buffer |= ((apsr >> 19) & 0b1) << bit_offsets[3];
 }
 
+// the same as UADD8, one lane at a time
+#[cfg(not(target_arch = "arm"))]
+pub fn pwm_pulse_batched(
+    current_values: &mut [u8; BATCH_SIZE],
+    target_values: &[u8; BATCH_SIZE],
+    bit_offsets: &[u32; BATCH_SIZE],
+    out_buffer: &mut u32,
+) {
+    for ((current_value, &target_value), &bit_offset) in current_values
+        .iter_mut()
+        .zip(target_values.iter())
+        .zip(bit_offsets.iter())
+    {
+        let (sum, overflowed) = current_value.overflowing_add(target_value);
+        *current_value = sum;
+        *out_buffer |= (overflowed as u32) << bit_offset;
+    }
+}
+
+pub fn bit_plane_batched(
+    target_values: &[u8; BATCH_SIZE],
+    bit_plane: u32,
+    bit_offsets: &[u32; BATCH_SIZE],
+    out_buffer: &mut u32,
+) {
+    // every lane keeps its wanted bit at the bottom of its byte
+    let plane_bits = u32::from_ne_bytes(*target_values) >> bit_plane;
+    *out_buffer |= (plane_bits & 0b1) << bit_offsets[0];
+    *out_buffer |= ((plane_bits >> 8) & 0b1) << bit_offsets[1];
+    *out_buffer |= ((plane_bits >> 16) & 0b1) << bit_offsets[2];
+    *out_buffer |= ((plane_bits >> 24) & 0b1) << bit_offsets[3];
+}
+
 pub const fn ns_to_cycles<const NS: u64>() -> u64 {
     (NS * (ARM_FREQUENCY as u64)).div_ceil(1_000_000_000_u64)
 }
 
+#[cfg(target_arch = "arm")]
 pub fn init_heap(heap: &Heap) {
     // // the runtime already has a dedicated spot in OCRAM for us to use as the heap
     // let heap_start = heap_start() as usize;
@@ -51,6 +93,7 @@ pub fn init_heap(heap: &Heap) {
     }
 }
 
+#[cfg(target_arch = "arm")]
 pub fn yield_cycles<const CYCLES: u64>() {
     const START_LOOP: u64 = 0xFFFFFF + SETUP_TIME_SINGLE + 2;
     // estimated using LLVM MCA
@@ -175,21 +218,25 @@ pub fn yield_cycles<const CYCLES: u64>() {
     }
 }
 
+#[cfg(target_arch = "arm")]
 fn systick_yield(cycles: u32) {
-    let mut systick = peripherals::syst();
-    systick.set_reload(cycles); // minus one here?
-    systick.clear_current();
-    systick.enable_counter();
-    // these must not be reordered
-    unsafe {
-        asm!("sev", "wfe", "wfe");
-    }
-    systick.disable_counter();
-    unsafe {
-        let scb = peripherals::scb();
-        // clear the pending systick interrupt
-        scb.icsr.modify(|icsr| icsr | (0b1 << 25));
-        // clear the pending systick exception
-        // scb.shcsr.modify(|shcsr| shcsr & !(0b1 << 11));
-    }
+    // only the wakeup is needed, so the exception is kept from being taken until it's cleared
+    cortex_m::interrupt::free(|_| {
+        let mut systick = peripherals::syst();
+        systick.set_reload(cycles); // minus one here?
+        systick.clear_current();
+        systick.enable_counter();
+        // these must not be reordered
+        unsafe {
+            asm!("sev", "wfe", "wfe");
+        }
+        systick.disable_counter();
+        unsafe {
+            let scb = peripherals::scb();
+            // clear the pending systick interrupt
+            scb.icsr.modify(|icsr| icsr | (0b1 << 25));
+            // clear the pending systick exception
+            // scb.shcsr.modify(|shcsr| shcsr & !(0b1 << 11));
+        }
+    });
 }
@@ -0,0 +1,141 @@
+use crate::color::{AdjustedColor, AdjustedColorRgba, BlendMode};
+use crate::framebuffer::{BackBuffer, Orientation, PANEL_HEIGHT, PANEL_WIDTH};
+
+// Layers are offscreen back buffers, so everything that can be drawn onto the screen can be drawn
+// onto a layer as well. They only get combined into the real back buffer right before the frame is
+// flipped, which lets a background effect, sprites and an overlay each be drawn on their own.
+
+pub struct Layer<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    pub canvas: BackBuffer<WIDTH, HEIGHT>,
+    pub visible: bool,
+    /// How much the layer covers the layers below it, where [`u8::MAX`] is fully opaque.
+    pub opacity: u8,
+    /// Where the top left corner of the canvas ends up on the screen. Whatever gets moved off the
+    /// screen is clipped, and the uncovered area shows the layers below.
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub blend_mode: BlendMode,
+    /// Pixels of this color are left out, so the layers below show through. Set it to black to
+    /// only cover anything with what has been drawn, since canvases start out black.
+    pub color_key: Option<AdjustedColor>,
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Layer<WIDTH, HEIGHT> {
+    pub const fn new() -> Self {
+        Self {
+            canvas: BackBuffer::new(),
+            visible: true,
+            opacity: u8::MAX,
+            offset_x: 0,
+            offset_y: 0,
+            blend_mode: BlendMode::Normal,
+            color_key: None,
+        }
+    }
+
+    /// Erases everything that has been drawn on the canvas, and keeps the settings.
+    pub fn clear(&mut self) {
+        self.canvas.clear();
+    }
+
+    fn composite_onto(&self, target: &mut BackBuffer<WIDTH, HEIGHT>) {
+        if !self.visible || self.opacity == 0 {
+            return;
+        }
+
+        let opaque = self.opacity == u8::MAX && self.blend_mode == BlendMode::Normal;
+
+        // only the part of the screen that the canvas overlaps
+        let screen_width = target.width() as i32;
+        let screen_height = target.height() as i32;
+        let start_x = self.offset_x.clamp(0, screen_width) as usize;
+        let end_x = self
+            .offset_x
+            .saturating_add(self.canvas.width() as i32)
+            .clamp(0, screen_width) as usize;
+        let start_y = self.offset_y.clamp(0, screen_height) as usize;
+        let end_y = self
+            .offset_y
+            .saturating_add(self.canvas.height() as i32)
+            .clamp(0, screen_height) as usize;
+
+        for y in start_y..end_y {
+            let canvas_y = (y as i32 - self.offset_y) as usize;
+
+            for x in start_x..end_x {
+                let canvas_x = (x as i32 - self.offset_x) as usize;
+                let color = self.canvas.get_led_adjusted(canvas_x, canvas_y);
+
+                if self.color_key == Some(color) {
+                    continue;
+                }
+
+                if opaque {
+                    target.set_led_adjusted(x, y, color);
+                } else {
+                    target.blend_led(
+                        x,
+                        y,
+                        AdjustedColorRgba::from_color(color, self.opacity),
+                        self.blend_mode,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Default for Layer<WIDTH, HEIGHT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fixed amount of layers, where the first one is at the bottom.
+pub struct LayerStack<
+    const COUNT: usize,
+    const WIDTH: usize = PANEL_WIDTH,
+    const HEIGHT: usize = PANEL_HEIGHT,
+> {
+    pub layers: [Layer<WIDTH, HEIGHT>; COUNT],
+}
+
+impl<const COUNT: usize, const WIDTH: usize, const HEIGHT: usize> LayerStack<COUNT, WIDTH, HEIGHT> {
+    const EMPTY_LAYER: Layer<WIDTH, HEIGHT> = Layer::new();
+
+    pub const fn new() -> Self {
+        Self {
+            layers: [Self::EMPTY_LAYER; COUNT],
+        }
+    }
+
+    pub fn layer_mut(&mut self, index: usize) -> &mut Layer<WIDTH, HEIGHT> {
+        &mut self.layers[index]
+    }
+
+    /// Gives every canvas the orientation of the screen, so the layers are drawn in the same
+    /// logical coordinates.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        for layer in &mut self.layers {
+            layer.canvas.set_orientation(orientation);
+        }
+    }
+
+    /// Replaces the contents of the back buffer with every visible layer, blended from the bottom
+    /// up over black. Meant to be called at the end of rendering, right before the flip.
+    pub fn composite(&self, target: &mut BackBuffer<WIDTH, HEIGHT>) {
+        target.clear();
+
+        for layer in &self.layers {
+            layer.composite_onto(target);
+        }
+    }
+}
+
+impl<const COUNT: usize, const WIDTH: usize, const HEIGHT: usize> Default
+    for LayerStack<COUNT, WIDTH, HEIGHT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
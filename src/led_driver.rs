@@ -1,16 +1,17 @@
-use core::hint::spin_loop;
-
-use cortex_m::peripheral::DWT;
-use teensy4_bsp::hal::ccm::clock_gate;
-use teensy4_bsp::hal::iomuxc::gpio::Pin;
-use teensy4_bsp::pins::t40::{ErasedPins, P2, P3};
-use teensy4_bsp::ral;
-use teensy4_bsp::ral::{modify_reg, read_reg, write_reg};
-
-use crate::framebuffer::{ColorLines, Framebuffer};
-use crate::intrinsics::{ns_to_cycles, pwm_pulse_batched, yield_cycles, BATCH_SIZE};
-use crate::peripherals;
+#[cfg(target_arch = "arm")]
+use teensy4_bsp::pins::t40::ErasedPins;
+
+use crate::framebuffer::{
+    ColorLines, FrameHandoff, Framebuffer, FrontBuffer, Orientation, FRAME_PERIOD_FRACTION_BITS,
+    PANEL_HEIGHT, PANEL_WIDTH,
+};
+use crate::intrinsics::{bit_plane_batched, ns_to_cycles, pwm_pulse_batched, BATCH_SIZE};
+use crate::output::OutputSink;
+#[cfg(target_arch = "arm")]
+use crate::output::{DmaOutput, FlexIoOutput, GpioOutput};
 use crate::pins::*;
+#[cfg(target_arch = "arm")]
+use crate::refresh;
 
 #[repr(u32)]
 #[rustfmt::skip]
@@ -57,197 +58,407 @@ impl FrameRate {
     }
 }
 
-#[derive(Copy, Clone)]
-enum DriverState {
-    ClockOn,
-    ClockOffDataOut,
+pub const RTC_FREQUENCY: u32 = 32768;
+// the core clock that prepare_clocks_and_power sets up
+pub const ARM_FREQUENCY: u32 = 600_000_000;
+#[cfg(target_arch = "arm")]
+const _: () = assert!(ARM_FREQUENCY == teensy4_bsp::board::ARM_FREQUENCY);
+
+// the longest period that fits in the fixed point representation, with one bit to spare so that
+// deadlines can be compared with wrapping arithmetic
+pub const MAX_FRAME_PERIOD_US: u32 = {
+    let max_ticks = (u32::MAX >> 1) as u64 >> FRAME_PERIOD_FRACTION_BITS;
+    ((max_ticks * 1_000_000) / RTC_FREQUENCY as u64) as u32
+};
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Modulation {
+    /// Dithers every LED with an 8-bit carry accumulator, see [`pwm_pulse_batched`].
+    Accumulator,
+    /// Binary code modulation. One bit plane of the frame is shifted out per shift cycle, and
+    /// each plane stays latched for a time slice weighted by its bit.
+    BitPlane,
+}
+
+const CLOCK_HIGH_CYCLES: u64 = ns_to_cycles::<22>();
+// the serial data has to be stable for 25ns before the next rising edge of the shift clock
+const DATA_SETUP_CYCLES: u64 = ns_to_cycles::<25>();
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum OutputMode {
+    /// The refresh interrupt writes every step to the pins itself.
+    Gpio,
+    /// Steps are computed ahead in batches, and streamed to the pins by the eDMA.
+    Dma,
+    /// The clock pulses are generated by FlexIO1, and the data lines are still written by the
+    /// CPU, from the FlexIO1 interrupt once the pulses are over. The column pins can't be reached
+    /// by the FlexIO that reaches the clock pins, so the data can't be shifted out in hardware on
+    /// this board.
+    FlexIo,
 }
 
-impl DriverState {
-    pub const fn pre_delay_cycles(&self) -> u32 {
-        match self {
-            DriverState::ClockOn => ns_to_cycles::<22>() as u32,
-            DriverState::ClockOffDataOut => ns_to_cycles::<25>() as u32,
+/// The render side of the screen. Programs draw into the back buffer, and the shift engine picks
+/// finished frames up from the refresh interrupt.
+pub struct ScreenDriver<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    pub framebuffer: Framebuffer<WIDTH, HEIGHT>,
+    handoff: &'static FrameHandoff<WIDTH, HEIGHT>,
+}
+
+#[cfg(target_arch = "arm")]
+impl ScreenDriver {
+    /// Sets up the output pins and starts refreshing the matrix from the timer interrupt. The
+    /// refresh interrupt only drives a panel of the size the firmware is built for.
+    pub fn new(
+        erased_pins: &mut ErasedPins,
+        modulation: Modulation,
+        output_mode: OutputMode,
+    ) -> Self {
+        let handoff = &refresh::FRAME_HANDOFF;
+
+        match output_mode {
+            OutputMode::Gpio => refresh::start(ShiftEngine::new(
+                GpioOutput::new(erased_pins, PANEL_WIDTH),
+                modulation,
+                handoff,
+            )),
+            OutputMode::Dma => refresh::start_dma(ShiftEngine::new(
+                DmaOutput::new(erased_pins, PANEL_WIDTH),
+                modulation,
+                handoff,
+            )),
+            OutputMode::FlexIo => refresh::start_flexio(ShiftEngine::new(
+                FlexIoOutput::new(erased_pins, PANEL_WIDTH),
+                modulation,
+                handoff,
+            )),
         }
+
+        Self::with_handoff(handoff)
     }
 }
 
-pub struct ScreenDriver {
-    rtc_mask: u32,
+impl<const WIDTH: usize, const HEIGHT: usize> ScreenDriver<WIDTH, HEIGHT> {
+    /// Creates a driver that submits its frames to the given handoff, without touching any
+    /// hardware. Whatever takes frames from the handoff has to be set up separately.
+    pub fn with_handoff(handoff: &'static FrameHandoff<WIDTH, HEIGHT>) -> Self {
+        Self {
+            framebuffer: Framebuffer::new(handoff),
+            handoff,
+        }
+    }
+
+    pub fn set_target_frame_rate(&mut self, frame_rate: FrameRate) {
+        self.handoff.set_rtc_mask(frame_rate.rtc_mask());
+        self.handoff.set_frame_period(0);
+    }
+
+    /// Paces frames at any rate, instead of the powers of two that [`FrameRate`] offers. Frames
+    /// still only get flipped at the end of a shift cycle, so the rate is only kept on average.
+    pub fn set_frame_rate_hz(&mut self, frame_rate_hz: u32) {
+        let frame_period =
+            ((RTC_FREQUENCY as u64) << FRAME_PERIOD_FRACTION_BITS) / frame_rate_hz.max(1) as u64;
+        self.handoff.set_frame_period(frame_period.max(1) as u32);
+    }
+
+    /// Like [`ScreenDriver::set_frame_rate_hz`], but with the time between frames. Periods are
+    /// capped at [`MAX_FRAME_PERIOD_US`].
+    pub fn set_frame_period_us(&mut self, frame_period_us: u32) {
+        let frame_period = ((frame_period_us.min(MAX_FRAME_PERIOD_US) as u64
+            * RTC_FREQUENCY as u64)
+            << FRAME_PERIOD_FRACTION_BITS)
+            / 1_000_000;
+        self.handoff.set_frame_period(frame_period.max(1) as u32);
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.framebuffer.back_buffer.orientation()
+    }
+
+    /// Turns and mirrors the image to match the way the panel is mounted. Programs should be
+    /// restarted afterwards, since they may have set things up for the previous size.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.framebuffer.back_buffer.clear();
+        self.framebuffer.back_buffer.set_orientation(orientation);
+    }
+
+    /// The width that programs draw in, after the orientation has been applied.
+    pub fn width(&self) -> usize {
+        self.framebuffer.back_buffer.width()
+    }
+
+    /// The height that programs draw in, after the orientation has been applied.
+    pub fn height(&self) -> usize {
+        self.framebuffer.back_buffer.height()
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.framebuffer.brightness()
+    }
+
+    /// Dims the whole matrix, starting from the next flipped frame. [`u8::MAX`] is full brightness,
+    /// and zero turns every LED off.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.framebuffer.set_brightness(brightness);
+    }
 
-    pub framebuffer: Framebuffer,
+    /// Flips the rendered frame and waits until it is being shown.
+    pub fn finish_frame(&mut self) {
+        self.framebuffer.flip();
+        self.framebuffer.wait_for_vsync();
+    }
+}
+
+/// The shift-out state machine. Every call to [`ShiftEngine::step`] shifts one bit into every
+/// column, and latches the shift registers once all of them have been filled.
+pub struct ShiftEngine<
+    O: OutputSink,
+    const WIDTH: usize = PANEL_WIDTH,
+    const HEIGHT: usize = PANEL_HEIGHT,
+> {
+    output: O,
+    modulation: Modulation,
+    handoff: &'static FrameHandoff<WIDTH, HEIGHT>,
+
+    front_buffer: FrontBuffer<WIDTH, HEIGHT>,
     pub current_shift_bit: u32,
-    state: DriverState,
-    delay_start_cycles: u32,
     clock_pulse_bits: u32,
     last_rtc_val: u32,
+    next_frame_time: u32,
+
+    current_step: u32,
+    current_bit_plane: u32,
+    displayed_bit_plane: u32,
+    latch_step: u32,
 }
 
-impl ScreenDriver {
-    pub const SHIFT_COUNT: u32 = (Framebuffer::HEIGHT * ColorLines::COUNT) as u32;
-
-    pub fn new(erased_pins: &mut ErasedPins) -> Self {
-        unsafe {
-            // configure LED output pins
-            for (&idx, &bit_offset) in LED_OUTPUT_PIN_INDICES
-                .iter()
-                .zip(GPIO6_BATCHED_PIN_OFFSETS.flatten().iter())
-            {
-                led_output_pin_setup(
-                    erased_pins.get_mut(idx as usize).unwrap_unchecked(),
-                    bit_offset,
-                );
-            }
+impl<O: OutputSink, const WIDTH: usize, const HEIGHT: usize> ShiftEngine<O, WIDTH, HEIGHT> {
+    pub const SHIFT_COUNT: u32 = (HEIGHT * ColorLines::COUNT) as u32;
+
+    pub const BIT_PLANE_COUNT: u32 = u8::BITS;
+    // The amount of steps the least significant bit plane stays latched. A slice can't be shorter
+    // than the time it takes to shift out the next plane.
+    pub const BIT_PLANE_SLICE_STEPS: u32 = Self::SHIFT_COUNT;
+
+    pub fn new(
+        output: O,
+        modulation: Modulation,
+        handoff: &'static FrameHandoff<WIDTH, HEIGHT>,
+    ) -> Self {
+        let mut engine = Self {
+            output,
+            modulation,
+            handoff,
+            front_buffer: FrontBuffer::new(),
+            current_shift_bit: 0,
+            clock_pulse_bits: 0,
+            last_rtc_val: 0,
+            next_frame_time: 0,
+            current_step: 0,
+            current_bit_plane: 0,
+            displayed_bit_plane: 0,
+            latch_step: 0,
+        };
 
-            // configure clock pins
-            clock_pin_setup(erased_pins.get_mut(2).unwrap_unchecked(), P2::OFFSET);
-            clock_pin_setup(erased_pins.get_mut(3).unwrap_unchecked(), P3::OFFSET);
-        }
+        // get the first serial output ready for the first clock pulse
+        engine.drive_data_out();
 
-        // enable SNVS HP clock gate because the RTC is on it
-        clock_gate::snvs_hp().set(&mut peripherals::ccm(), clock_gate::ON);
+        engine
+    }
 
-        // enable RTC and wait for it to get set
-        let snvs = peripherals::snvs();
-        modify_reg!(ral::snvs, snvs, HPCR, RTC_EN: RTC_EN_1);
-        while read_reg!(ral::snvs, snvs, HPCR, RTC_EN != RTC_EN_1) {
-            spin_loop();
-        }
+    pub fn output(&self) -> &O {
+        &self.output
+    }
 
-        Self {
-            rtc_mask: FrameRate::Fps64.rtc_mask(),
-            framebuffer: Framebuffer::default(),
-            current_shift_bit: 0,
-            state: DriverState::ClockOn,
-            delay_start_cycles: DWT::cycle_count(),
-            clock_pulse_bits: 0,
-            last_rtc_val: 0,
-        }
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
     }
 
-    pub fn set_target_frame_rate(&mut self, frame_rate: FrameRate) {
-        self.rtc_mask = frame_rate.rtc_mask();
+    pub fn modulation(&self) -> Modulation {
+        self.modulation
     }
 
-    pub fn drive_mid_render(&mut self) {
-        if DWT::cycle_count().wrapping_sub(self.delay_start_cycles) >= self.state.pre_delay_cycles()
-        {
-            match self.state {
-                DriverState::ClockOn => {
-                    self.drive_clock_on();
-                    self.state = DriverState::ClockOffDataOut;
-                }
-                DriverState::ClockOffDataOut => {
-                    self.drive_clock_off_data_out::<false>();
-                    self.state = DriverState::ClockOn;
-                }
+    /// The amount of steps it takes for every LED to go through its whole duty cycle once.
+    pub fn modulation_period_steps(&self) -> u32 {
+        match self.modulation {
+            Modulation::Accumulator => (u8::MAX as u32 + 1) * Self::SHIFT_COUNT,
+            Modulation::BitPlane => {
+                ((0b1 << Self::BIT_PLANE_COUNT) - 1) * Self::BIT_PLANE_SLICE_STEPS
             }
-
-            self.delay_start_cycles = DWT::cycle_count();
         }
     }
 
-    pub fn drive_post_render(&mut self) {
-        let mut frame_flipped = false;
+    /// Clocks out a single bit. The serial output for the next bit is written before returning, so
+    /// the time between steps has to satisfy the 25ns setup time of the shift registers.
+    pub fn step(&mut self) {
+        self.current_step = self.current_step.wrapping_add(1);
 
-        while !frame_flipped {
-            match self.state {
-                DriverState::ClockOn => {
-                    yield_cycles::<{ DriverState::ClockOn.pre_delay_cycles() as u64 }>();
-                    self.drive_clock_on();
-                    self.state = DriverState::ClockOffDataOut;
-                }
-                DriverState::ClockOffDataOut => {
-                    yield_cycles::<{ DriverState::ClockOffDataOut.pre_delay_cycles() as u64 }>();
-                    frame_flipped = self.drive_clock_off_data_out::<true>();
-                    self.state = DriverState::ClockOn;
-                }
-            }
+        if !self.bit_plane_slice_elapsed() {
+            self.output.hold();
+            return;
+        }
+
+        self.drive_clock_on();
+        self.output.wait_cycles::<CLOCK_HIGH_CYCLES>();
+        self.output.clock_off(self.clock_pulse_bits);
+
+        // between the clock pulse and the serial output changing, 3 cycles of delay is expected.
+        // in any scenario, this is already satisfied by the code setting up the next serial output,
+        // so it should be fine to exclude an excess yield.
+
+        self.current_shift_bit += 1;
+        if self.current_shift_bit == Self::SHIFT_COUNT {
+            self.current_shift_bit = 0;
+            self.advance_frame();
         }
+
+        self.drive_data_out();
+    }
+
+    /// Runs a whole shift cycle worth of steps back to back. Latches only ever happen on the first
+    /// step of a shift cycle, so calling this at a fixed rate keeps every latched cycle on the
+    /// matrix for the same time, while only needing a fraction of the interrupts.
+    pub fn step_shift_cycle(&mut self) {
+        for _ in 0..Self::SHIFT_COUNT {
+            self.step();
+            self.output.wait_cycles::<DATA_SETUP_CYCLES>();
+        }
+    }
+
+    // the latch pulse is held back until the currently displayed bit plane has been shown for its
+    // whole time slice
+    fn bit_plane_slice_elapsed(&self) -> bool {
+        self.modulation != Modulation::BitPlane
+            || self.current_shift_bit != 0
+            || self.current_step.wrapping_sub(self.latch_step)
+                >= Self::BIT_PLANE_SLICE_STEPS << self.displayed_bit_plane
     }
 
     fn drive_clock_on(&mut self) {
-        self.clock_pulse_bits = 0b1 << P3::OFFSET;
+        self.clock_pulse_bits = 0b1 << SHIFT_CLOCK_OFFSET;
         self.clock_pulse_bits |= if self.current_shift_bit == 0 {
-            0b1 << P2::OFFSET
+            0b1 << LATCH_CLOCK_OFFSET
         } else {
             0
         };
 
-        write_reg!(
-            ral::gpio,
-            peripherals::gpio9(),
-            DR_SET,
-            self.clock_pulse_bits
-        );
+        self.output.clock_on(self.clock_pulse_bits);
+
+        if self.modulation == Modulation::BitPlane && self.current_shift_bit == 0 {
+            // the latch stores the plane that was shifted out during the last shift cycle
+            self.latch_step = self.current_step;
+            self.displayed_bit_plane =
+                (self.current_bit_plane + Self::BIT_PLANE_COUNT - 1) % Self::BIT_PLANE_COUNT;
+        }
     }
 
-    fn drive_clock_off_data_out<const ALLOW_FRAME_FLIP: bool>(&mut self) -> bool {
-        write_reg!(
-            ral::gpio,
-            peripherals::gpio9(),
-            DR_CLEAR,
-            self.clock_pulse_bits
-        );
+    fn advance_frame(&mut self) {
+        let frame_complete = match self.modulation {
+            Modulation::Accumulator => true,
+            Modulation::BitPlane => {
+                self.current_bit_plane += 1;
+                if self.current_bit_plane == Self::BIT_PLANE_COUNT {
+                    self.current_bit_plane = 0;
+                }
+                // flipping between planes would mix two frames in one modulation period
+                self.current_bit_plane == 0
+            }
+        };
+
+        if !frame_complete {
+            return;
+        }
 
-        // between the clock pulse and the serial output changing, 3 cycles of delay is expected.
-        // in any scenario, this is already satisfied by the code setting up the next serial output,
-        // so it should be fine to exclude an excess yield.
+        // Frame advance is done here to effectively cause a vertical sync, as we
+        // will only be updating the FB after all scanlines are written. If the next frame isn't
+        // done yet, it gets picked up at the end of the first shift cycle after it is.
+        let frame_period = self.handoff.frame_period();
+        if frame_period == 0 {
+            // the mask chooses which bits are tested against, which can effectively set the
+            // framerate
+            let current_rtc_val = self.output.rtc_value() & self.handoff.rtc_mask();
+
+            if self.last_rtc_val != current_rtc_val
+                && self.handoff.take(&mut self.front_buffer.bit_target_lines)
+            {
+                self.last_rtc_val = current_rtc_val;
+            }
+        } else {
+            self.advance_paced_frame(frame_period);
+        }
+    }
 
-        let mut frame_flipped = false;
+    // Frames are due every period, counted in fixed point RTC ticks so that rates which don't
+    // divide the RTC frequency still average out. The times wrap around, so they're only compared
+    // through their difference.
+    fn advance_paced_frame(&mut self, frame_period: u32) {
+        let now = self.output.rtc_value() << FRAME_PERIOD_FRACTION_BITS;
 
-        self.current_shift_bit += 1;
-        if self.current_shift_bit == Self::SHIFT_COUNT {
-            self.current_shift_bit = 0;
+        // a deadline further away than a whole period is left over from a different rate
+        if self.next_frame_time.wrapping_sub(now) as i32 > frame_period as i32 {
+            self.next_frame_time = now;
+        }
 
-            if ALLOW_FRAME_FLIP {
-                // the mask chooses which bits are tested against, which can effectively set the
-                // framerate
-                let current_rtc_val =
-                    read_reg!(ral::snvs, peripherals::snvs(), HPRTCLR) & self.rtc_mask;
-
-                // Frame advance is done here to effectively cause a vertical sync, as we
-                // will only be updating the FB after all scanlines are written.
-                if self.last_rtc_val != current_rtc_val {
-                    self.last_rtc_val = current_rtc_val;
-                    self.framebuffer.flip();
-                    frame_flipped = true;
-                }
+        if self.next_frame_time.wrapping_sub(now) as i32 <= 0
+            && self.handoff.take(&mut self.front_buffer.bit_target_lines)
+        {
+            self.next_frame_time = self.next_frame_time.wrapping_add(frame_period);
+
+            // frames that were missed completely aren't caught up on
+            if self.next_frame_time.wrapping_sub(now) as i32 <= 0 {
+                self.next_frame_time = now.wrapping_add(frame_period);
             }
         }
+    }
 
+    fn drive_data_out(&mut self) {
         let target_values = unsafe {
-            self.framebuffer
-                .front_buffer
+            self.front_buffer
                 .bit_target_lines
-                .get_mut(self.current_shift_bit as usize)
-                .unwrap_unchecked()
-        };
-        let current_values = unsafe {
-            self.framebuffer
-                .front_buffer
-                .bit_current_lines
-                .get_mut(self.current_shift_bit as usize)
+                .flatten()
+                .get(self.current_shift_bit as usize)
                 .unwrap_unchecked()
         };
 
         let mut gpio6_out_buffer = 0_u32;
 
-        for ((current_value_batch, target_value_batch), pin_offset_batch) in current_values
-            .array_chunks_mut::<BATCH_SIZE>()
-            .zip(target_values.array_chunks::<BATCH_SIZE>())
-            .zip(GPIO6_BATCHED_PIN_OFFSETS.iter())
-        {
-            pwm_pulse_batched(
-                current_value_batch,
-                target_value_batch,
-                pin_offset_batch,
-                &mut gpio6_out_buffer,
-            );
+        match self.modulation {
+            Modulation::Accumulator => {
+                let current_values = unsafe {
+                    self.front_buffer
+                        .bit_current_lines
+                        .flatten_mut()
+                        .get_mut(self.current_shift_bit as usize)
+                        .unwrap_unchecked()
+                };
+
+                for ((current_value_batch, target_value_batch), pin_offset_batch) in current_values
+                    .array_chunks_mut::<BATCH_SIZE>()
+                    .zip(target_values.array_chunks::<BATCH_SIZE>())
+                    .zip(GPIO6_BATCHED_PIN_OFFSETS.iter())
+                {
+                    pwm_pulse_batched(
+                        current_value_batch,
+                        target_value_batch,
+                        pin_offset_batch,
+                        &mut gpio6_out_buffer,
+                    );
+                }
+            }
+            Modulation::BitPlane => {
+                for (target_value_batch, pin_offset_batch) in target_values
+                    .array_chunks::<BATCH_SIZE>()
+                    .zip(GPIO6_BATCHED_PIN_OFFSETS.iter())
+                {
+                    bit_plane_batched(
+                        target_value_batch,
+                        self.current_bit_plane,
+                        pin_offset_batch,
+                        &mut gpio6_out_buffer,
+                    );
+                }
+            }
         }
 
-        write_reg!(ral::gpio, peripherals::gpio6(), DR, gpio6_out_buffer);
-
-        frame_flipped
+        self.output.data_out(gpio6_out_buffer);
     }
 }
@@ -0,0 +1,83 @@
+#[cfg(target_arch = "arm")]
+use teensy4_bsp::hal::snvs::srtc::Srtc;
+#[cfg(target_arch = "arm")]
+use teensy4_bsp::hal::snvs::{self, LowPower, LpCore};
+#[cfg(target_arch = "arm")]
+use teensy4_bsp::ral::{self, read_reg, write_reg};
+
+#[cfg(target_arch = "arm")]
+use crate::peripherals;
+
+// The SNVS low power domain keeps running on the coin cell while the board is off: the SRTC keeps
+// counting seconds, and a few general purpose registers keep their values. The clock, the alarms
+// and the settings only reach it through this trait, so they can run against a simulated domain on
+// the host.
+
+/// The amount of general purpose registers.
+pub const LOW_POWER_REGISTERS: usize = 4;
+
+#[cfg(target_arch = "arm")]
+pub type PlatformLowPower = SnvsLowPower;
+#[cfg(not(target_arch = "arm"))]
+pub type PlatformLowPower = crate::output::SimulatedLowPower;
+
+pub trait LowPowerDomain {
+    /// Gets a handle to the domain, and starts the SRTC if it isn't running yet. Every handle
+    /// reads and writes the same counter and registers.
+    fn open() -> Self
+    where
+        Self: Sized;
+
+    /// The SRTC seconds, and the microseconds into the current second.
+    fn time_with_micros(&self) -> (u32, u32);
+
+    fn time(&self) -> u32 {
+        self.time_with_micros().0
+    }
+
+    /// Sets the SRTC seconds, and starts the second over.
+    fn set_time(&mut self, seconds: u32);
+
+    fn register(&self, index: usize) -> u32;
+
+    fn set_register(&mut self, index: usize, value: u32);
+}
+
+#[cfg(target_arch = "arm")]
+pub struct SnvsLowPower {
+    srtc: Srtc,
+    core: LpCore,
+}
+
+#[cfg(target_arch = "arm")]
+impl LowPowerDomain for SnvsLowPower {
+    fn open() -> Self {
+        let LowPower {
+            mut core,
+            srtc: raw_srtc,
+            ..
+        } = snvs::new(peripherals::snvs()).low_power;
+
+        let srtc = raw_srtc.enable(&mut core);
+
+        Self { srtc, core }
+    }
+
+    fn time_with_micros(&self) -> (u32, u32) {
+        self.srtc.get_with_micros()
+    }
+
+    fn set_time(&mut self, seconds: u32) {
+        self.srtc.set(&mut self.core, seconds, 0);
+    }
+
+    fn register(&self, index: usize) -> u32 {
+        debug_assert!(index < LOW_POWER_REGISTERS);
+        read_reg!(ral::snvs, peripherals::snvs(), LPGPR[index])
+    }
+
+    fn set_register(&mut self, index: usize, value: u32) {
+        debug_assert!(index < LOW_POWER_REGISTERS);
+        write_reg!(ral::snvs, peripherals::snvs(), LPGPR[index], value);
+    }
+}
@@ -4,42 +4,82 @@
 #![feature(slice_flatten)]
 #![feature(exclusive_range_pattern)]
 #![feature(maybe_uninit_slice)]
-#![no_std]
-#![no_main]
+// Host builds keep the standard library, and run the driver against the simulated output.
+#![cfg_attr(target_arch = "arm", no_std, no_main)]
 
 extern crate alloc;
 
+mod alarm;
+mod antialias;
 mod button;
 mod collections;
 mod color;
+mod draw;
+mod font;
 mod framebuffer;
 mod intrinsics;
+mod layer;
 mod led_driver;
+mod low_power;
+mod output;
+mod palette;
+#[cfg(target_arch = "arm")]
 mod peripherals;
 mod pins;
 mod program;
-
+#[cfg(target_arch = "arm")]
+mod refresh;
+mod settings;
+mod sprite;
+mod time;
+
+#[cfg(target_arch = "arm")]
+use alloc::format;
+#[cfg(target_arch = "arm")]
+use alloc::string::String;
+#[cfg(target_arch = "arm")]
 use core::arch::asm;
 
+#[cfg(target_arch = "arm")]
+use chrono::{Datelike, NaiveDate};
+#[cfg(target_arch = "arm")]
 use cortex_m::interrupt;
+#[cfg(target_arch = "arm")]
 use cortex_m::peripheral::syst::SystClkSource;
+#[cfg(target_arch = "arm")]
 use cortex_m::register::basepri;
+#[cfg(target_arch = "arm")]
 use embedded_alloc::Heap;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::board::prepare_clocks_and_power;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::hal::iomuxc::into_pads;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::t40::*;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::ral::{self, modify_reg};
+#[cfg(target_arch = "arm")]
 #[allow(unused_imports)]
 use teensy4_panic as _;
 
-use crate::button::Button;
+#[cfg(target_arch = "arm")]
+use crate::alarm::AlarmClock;
+#[cfg(target_arch = "arm")]
+use crate::button::{Button, ButtonEvent};
+#[cfg(target_arch = "arm")]
 use crate::intrinsics::init_heap;
-use crate::led_driver::ScreenDriver;
+use crate::led_driver::Modulation;
+#[cfg(target_arch = "arm")]
+use crate::led_driver::{OutputMode, ScreenDriver};
+#[cfg(not(target_arch = "arm"))]
+use crate::output::SimulatedScreen;
 use crate::program::*;
 
+#[cfg(target_arch = "arm")]
 #[global_allocator]
 static mut HEAP: Heap = Heap::empty();
 
+#[cfg(target_arch = "arm")]
 #[teensy4_bsp::rt::entry]
 fn main() -> ! {
     interrupt::disable();
@@ -84,22 +124,92 @@ fn main() -> ! {
     let mut erased_pins = pins.erase();
 
     let mut button = Button::new(&mut erased_pins[5]);
-    let mut led_driver = ScreenDriver::new(&mut erased_pins);
+    let mut led_driver =
+        ScreenDriver::new(&mut erased_pins, Modulation::Accumulator, OutputMode::Gpio);
 
     let mut program_index = 0;
     let mut current_program = PROGRAM_CONSTRUCTORS[program_index](&mut led_driver);
 
-    loop {
-        current_program.render(&mut led_driver);
-        led_driver.drive_post_render();
+    let mut alarm_clock = AlarmClock::new();
+    let mut alarm_active = false;
 
-        button.check_if_pressed(|| {
-            program_index += 1;
-            if program_index >= PROGRAM_CONSTRUCTORS.len() {
-                program_index = 0;
-            }
+    // unless it has been given a message, the marquee shows the local date, and moves on to the
+    // next one at midnight
+    let mut marquee_date = None;
 
+    loop {
+        let today = time::local_now().date_naive();
+        if marquee_date != Some(today) {
+            marquee_date = Some(today);
+            MARQUEE_CONTROL.set_default_message(date_message(today));
+        }
+
+        // a ringing alarm or a sunrise takes over the panel, and the program starts over once it
+        // is done
+        let alarm_was_active = alarm_active;
+        alarm_active = alarm_clock.update();
+        if alarm_active && !alarm_was_active {
+            led_driver.set_target_frame_rate(AlarmClock::FRAME_RATE);
+        } else if !alarm_active && alarm_was_active {
             current_program = PROGRAM_CONSTRUCTORS[program_index](&mut led_driver);
-        });
+        }
+
+        if alarm_active {
+            alarm_clock.render(&mut led_driver);
+        } else {
+            current_program.render(&mut led_driver);
+        }
+        led_driver.finish_frame();
+
+        // the program gets the first say, so it can use the button for its own settings
+        if let Some(event) = button.poll() {
+            if alarm_clock.handle_button(event) {
+                // used to snooze or turn off the alarm
+            } else if !current_program.handle_button(event, &mut led_driver)
+                && event == ButtonEvent::ShortPress
+            {
+                program_index += 1;
+                if program_index >= PROGRAM_CONSTRUCTORS.len() {
+                    program_index = 0;
+                }
+
+                current_program = PROGRAM_CONSTRUCTORS[program_index](&mut led_driver);
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "arm")]
+fn date_message(date: NaiveDate) -> String {
+    const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    format!(
+        "{} {} {}",
+        WEEKDAYS[date.weekday().num_days_from_monday() as usize],
+        date.day(),
+        MONTHS[date.month0() as usize]
+    )
+}
+
+// Off the Teensy, every program is run through the simulated shift registers instead, and the
+// first frame that the matrix would show is printed to the terminal.
+#[cfg(not(target_arch = "arm"))]
+fn main() {
+    let mut screen: SimulatedScreen = SimulatedScreen::new(Modulation::Accumulator);
+
+    for constructor in PROGRAM_CONSTRUCTORS {
+        let mut program = constructor(&mut screen.driver);
+        let frame = screen.render_frame(program.as_mut());
+
+        for row in frame.iter() {
+            for color in row.iter().map(|color| color.to_color()) {
+                print!("\x1b[48;2;{};{};{}m  ", color.r, color.g, color.b);
+            }
+            println!("\x1b[0m");
+        }
+        println!();
     }
 }
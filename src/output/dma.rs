@@ -0,0 +1,260 @@
+use teensy4_bsp::hal::ccm::clock_gate;
+use teensy4_bsp::hal::iomuxc::gpio::Pin;
+use teensy4_bsp::pins::t40::{ErasedPins, P2, P3};
+use teensy4_bsp::ral;
+use teensy4_bsp::ral::{read_reg, write_reg};
+
+use super::gpio::{enable_rtc, read_rtc, setup_output_pins};
+use super::toggle_stream::ToggleStream;
+use super::OutputSink;
+use crate::peripherals;
+use crate::pins::*;
+
+// Every step of the shift engine turns into two ticks, one with the clocks raised and one with them
+// lowered. On every tick of the pacing timer, DATA_CHANNEL writes a data word to GPIO1, and then
+// links to CLOCK_CHANNEL which writes the matching clock word to GPIO4. The words go to DR_TOGGLE,
+// and only hold the lines that change from the previous tick, so the other pins on those ports are
+// left alone. The words are worked out by a ToggleStream.
+//
+// The buffer is streamed in a loop, and the half that was just sent gets refilled from the DMA
+// interrupt while the other half is being sent.
+
+pub const DMA_STEPS_PER_HALF: usize = 127;
+// with minor loop linking, the major loop count only has 9 bits
+const TICK_COUNT: usize = DMA_STEPS_PER_HALF * 2 * 2;
+const _: () = assert!(TICK_COUNT < (1 << 9));
+
+pub const DATA_CHANNEL: usize = 0;
+pub const CLOCK_CHANNEL: usize = 1;
+
+// these end up in DTCM, which the DMA reaches through the AHB slave port, and which isn't cached
+static mut DATA_WORDS: [u32; TICK_COUNT] = [0; TICK_COUNT];
+static mut CLOCK_WORDS: [u32; TICK_COUNT] = [0; TICK_COUNT];
+
+/// Streams the shift register output with the eDMA instead of writing the pins directly. Writes
+/// only fill the word buffers, which get sent out by the DMA at the pace of the step timer.
+pub struct DmaOutput {
+    stream: ToggleStream<TICK_COUNT>,
+}
+
+impl DmaOutput {
+    pub fn new(erased_pins: &mut ErasedPins, column_count: usize) -> Self {
+        setup_output_pins(erased_pins, column_count);
+        enable_rtc();
+
+        // GPIO1 and GPIO4 have to be clocked before their direction registers can be written
+        let ccm = &mut peripherals::ccm();
+        clock_gate::gpio::<1>().set(ccm, clock_gate::ON);
+        clock_gate::gpio::<4>().set(ccm, clock_gate::ON);
+
+        let data_pin_offsets = GPIO6_BATCHED_PIN_OFFSETS
+            .flatten()
+            .iter()
+            .take(column_count);
+
+        // the stream toggles from all lines low, which has to be the case before GPIO1 and GPIO4
+        // take the pins over
+        let data_pin_mask = data_pin_offsets
+            .clone()
+            .fold(0, |mask, &bit_offset| mask | (0b1 << bit_offset));
+        write_reg!(ral::gpio, peripherals::gpio1(), DR_CLEAR, data_pin_mask);
+        write_reg!(
+            ral::gpio,
+            peripherals::gpio4(),
+            DR_CLEAR,
+            (0b1 << P2::OFFSET) | (0b1 << P3::OFFSET)
+        );
+
+        for &bit_offset in data_pin_offsets {
+            led_output_pin_use_normal_gpio(bit_offset);
+        }
+        clock_pin_use_normal_gpio(P2::OFFSET);
+        clock_pin_use_normal_gpio(P3::OFFSET);
+
+        Self {
+            stream: ToggleStream::new(),
+        }
+    }
+
+    /// Moves the write cursor to the start of the given buffer half. Whatever was written before
+    /// the first seek, like the first data word of a new engine, is left out of the stream.
+    pub fn seek(&mut self, half: usize) {
+        self.stream.seek(half);
+    }
+
+    /// The buffer half that the DMA has just finished sending, and can be refilled.
+    pub fn finished_half(&self) -> usize {
+        let dma = peripherals::dma();
+        let current_iteration = read_reg!(
+            ral::dma::tcd,
+            &dma.TCD[DATA_CHANNEL],
+            TCD_CITER_ELINKNO,
+            CITER
+        ) as usize
+            & 0x1FF;
+
+        // the count goes down, and gets reloaded when the major loop completes
+        if current_iteration <= TICK_COUNT / 2 {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Starts streaming the buffers. Both halves have to be filled before calling this, and the
+    /// pacing timer has to be started afterwards.
+    pub fn start_stream(&mut self) {
+        let ccm = &mut peripherals::ccm();
+        clock_gate::dma().set(ccm, clock_gate::ON);
+
+        let dma = peripherals::dma();
+        let buffer_size = (TICK_COUNT * core::mem::size_of::<u32>()) as u32;
+        // 32-bit source and destination
+        let attributes = (0b010 << 8) | 0b010;
+
+        unsafe {
+            let data_tcd = &dma.TCD[DATA_CHANNEL];
+            write_reg!(
+                ral::dma::tcd,
+                data_tcd,
+                TCD_SADDR,
+                DATA_WORDS.as_ptr() as u32
+            );
+            write_reg!(ral::dma::tcd, data_tcd, TCD_SOFF, 4);
+            write_reg!(ral::dma::tcd, data_tcd, TCD_ATTR, attributes);
+            write_reg!(ral::dma::tcd, data_tcd, TCD_NBYTES_MLNO, 4);
+            write_reg!(
+                ral::dma::tcd,
+                data_tcd,
+                TCD_SLAST,
+                buffer_size.wrapping_neg()
+            );
+            write_reg!(
+                ral::dma::tcd,
+                data_tcd,
+                TCD_DADDR,
+                &peripherals::gpio1().DR_TOGGLE as *const _ as u32
+            );
+            write_reg!(ral::dma::tcd, data_tcd, TCD_DOFF, 0);
+            write_reg!(ral::dma::tcd, data_tcd, TCD_DLASTSGA, 0);
+            // link to the clock channel after every minor loop
+            let linked_iterations = (0b1 << 15) | ((CLOCK_CHANNEL as u16) << 9) | TICK_COUNT as u16;
+            write_reg!(
+                ral::dma::tcd,
+                data_tcd,
+                TCD_CITER_ELINKNO,
+                linked_iterations
+            );
+            write_reg!(
+                ral::dma::tcd,
+                data_tcd,
+                TCD_BITER_ELINKNO,
+                linked_iterations
+            );
+            // the minor loop link doesn't happen on the last iteration, so the major loop has to
+            // link as well
+            write_reg!(
+                ral::dma::tcd,
+                data_tcd,
+                TCD_CSR,
+                INTMAJOR: 1,
+                INTHALF: 1,
+                MAJORELINK: 1,
+                MAJORLINKCH: CLOCK_CHANNEL as u16
+            );
+
+            let clock_tcd = &dma.TCD[CLOCK_CHANNEL];
+            write_reg!(
+                ral::dma::tcd,
+                clock_tcd,
+                TCD_SADDR,
+                CLOCK_WORDS.as_ptr() as u32
+            );
+            write_reg!(ral::dma::tcd, clock_tcd, TCD_SOFF, 4);
+            write_reg!(ral::dma::tcd, clock_tcd, TCD_ATTR, attributes);
+            write_reg!(ral::dma::tcd, clock_tcd, TCD_NBYTES_MLNO, 4);
+            write_reg!(
+                ral::dma::tcd,
+                clock_tcd,
+                TCD_SLAST,
+                buffer_size.wrapping_neg()
+            );
+            write_reg!(
+                ral::dma::tcd,
+                clock_tcd,
+                TCD_DADDR,
+                &peripherals::gpio4().DR_TOGGLE as *const _ as u32
+            );
+            write_reg!(ral::dma::tcd, clock_tcd, TCD_DOFF, 0);
+            write_reg!(ral::dma::tcd, clock_tcd, TCD_DLASTSGA, 0);
+            write_reg!(
+                ral::dma::tcd,
+                clock_tcd,
+                TCD_CITER_ELINKNO,
+                TICK_COUNT as u16
+            );
+            write_reg!(
+                ral::dma::tcd,
+                clock_tcd,
+                TCD_BITER_ELINKNO,
+                TICK_COUNT as u16
+            );
+            write_reg!(ral::dma::tcd, clock_tcd, TCD_CSR, 0);
+        }
+
+        // only the first 4 DMAMUX channels can be triggered periodically, each by the PIT channel
+        // with the same number
+        write_reg!(
+            ral::dmamux,
+            peripherals::dmamux(),
+            CHCFG[DATA_CHANNEL],
+            ENBL: 1,
+            TRIG: 1,
+            A_ON: 1
+        );
+        write_reg!(ral::dma, dma, SERQ, DATA_CHANNEL as u8);
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        write_reg!(ral::dma, peripherals::dma(), CINT, DATA_CHANNEL as u8);
+    }
+
+    fn push_tick(&mut self) {
+        let tick = self.stream.next_tick();
+
+        unsafe {
+            *DATA_WORDS.get_unchecked_mut(tick.slot) = tick.data_word;
+            *CLOCK_WORDS.get_unchecked_mut(tick.slot) = tick.clock_word;
+        }
+    }
+}
+
+impl OutputSink for DmaOutput {
+    fn clock_on(&mut self, clock_bits: u32) {
+        self.stream.clock_bits |= clock_bits;
+        self.push_tick();
+    }
+
+    fn clock_off(&mut self, clock_bits: u32) {
+        // goes out together with the next data word
+        self.stream.clock_bits &= !clock_bits;
+    }
+
+    fn data_out(&mut self, data_bits: u32) {
+        self.stream.data_bits = data_bits;
+        self.push_tick();
+    }
+
+    fn wait_cycles<const CYCLES: u64>(&mut self) {
+        // every tick already lasts a whole timer period
+    }
+
+    fn hold(&mut self) {
+        self.push_tick();
+        self.push_tick();
+    }
+
+    fn rtc_value(&mut self) -> u32 {
+        read_rtc()
+    }
+}
@@ -0,0 +1,202 @@
+use teensy4_bsp::hal::ccm::clock_gate;
+use teensy4_bsp::hal::iomuxc::gpio::Pin;
+use teensy4_bsp::pins::imxrt_iomuxc::flexio;
+use teensy4_bsp::pins::t40::{ErasedPins, P2, P3};
+use teensy4_bsp::ral;
+use teensy4_bsp::ral::{modify_reg, read_reg, write_reg};
+
+use super::gpio::{enable_rtc, read_rtc, setup_output_pins};
+use super::OutputSink;
+use crate::peripherals;
+use crate::pins::*;
+
+// Each clock line gets a FlexIO1 shifter and timer pair. Writing to the shifter buffer triggers
+// its timer, which runs a single baud period on the clock pin: low for half of it, then high for
+// the other half. The shifters themselves don't drive any pin, they only exist to trigger the
+// timers. Once a pulse is over, its timer raises the FlexIO1 interrupt, which writes the data for
+// the next step and takes it (see refresh.rs).
+//
+// Only the clocks are generated in hardware. The data lines stay on GPIO6, because pins 0, 1, 24
+// and 25 sit on pads without any FlexIO signal, and the rest of them are only reachable by FlexIO3,
+// which can't reach the clock pins. Shifting the data out with FlexIO as well would need a board
+// with the columns and clocks rewired onto FlexIO3 pads.
+
+// pll3_sw_clk (480 MHz) / 2 / 2
+const FLEXIO_FREQUENCY: u64 = 120_000_000;
+
+const fn ns_to_flexio_cycles<const NS: u64>() -> u64 {
+    (NS * FLEXIO_FREQUENCY).div_ceil(1_000_000_000_u64)
+}
+
+// the high half of the pulse has to satisfy the 22ns clock pulse width. the data only changes once
+// the previous pulse is over, so the low half in front of the rising edge covers the 25ns data
+// setup time.
+const PULSE_HALF_CYCLES: u64 = ns_to_flexio_cycles::<25>();
+const _: () = assert!(PULSE_HALF_CYCLES <= 256);
+
+// the upper byte is the amount of edges minus one, the lower byte is the baud divider
+const PULSE_COMPARE: u32 = (1 << 8) | (PULSE_HALF_CYCLES as u32 - 1);
+
+const SHIFT_CLOCK_CHANNEL: usize = 0;
+const LATCH_CLOCK_CHANNEL: usize = 1;
+
+/// Generates the clock pulses with FlexIO1, so their width is exact no matter what the CPU is
+/// doing. The data lines are still driven through GPIO6, and are held back until the pulses are
+/// over.
+pub struct FlexIoOutput {
+    pending_channels: u32,
+    pending_data_bits: u32,
+}
+
+impl FlexIoOutput {
+    pub fn new(erased_pins: &mut ErasedPins, column_count: usize) -> Self {
+        setup_output_pins(erased_pins, column_count);
+        enable_rtc();
+
+        unsafe {
+            clock_pin_flexio_setup(
+                erased_pins.get_mut(2).unwrap_unchecked(),
+                <P2 as flexio::Pin<1>>::ALT,
+            );
+            clock_pin_flexio_setup(
+                erased_pins.get_mut(3).unwrap_unchecked(),
+                <P3 as flexio::Pin<1>>::ALT,
+            );
+        }
+
+        let ccm = &mut peripherals::ccm();
+        // the divider can only be changed while the clock is gated
+        clock_gate::flexio::<1>().set(ccm, clock_gate::OFF);
+        modify_reg!(
+            ral::ccm,
+            peripherals::ccm(),
+            CDCDR,
+            FLEXIO1_CLK_SEL: 0b11,
+            FLEXIO1_CLK_PRED: 0b001,
+            FLEXIO1_CLK_PODF: 0b001
+        );
+        clock_gate::flexio::<1>().set(ccm, clock_gate::ON);
+
+        let flexio = peripherals::flexio1();
+        write_reg!(ral::flexio, flexio, CTRL, SWRST: 1);
+        write_reg!(ral::flexio, flexio, CTRL, SWRST: 0);
+
+        setup_pulse_channel(SHIFT_CLOCK_CHANNEL, <P3 as flexio::Pin<1>>::OFFSET);
+        setup_pulse_channel(LATCH_CLOCK_CHANNEL, <P2 as flexio::Pin<1>>::OFFSET);
+
+        write_reg!(
+            ral::flexio,
+            flexio,
+            TIMIEN,
+            (0b1 << SHIFT_CLOCK_CHANNEL) | (0b1 << LATCH_CLOCK_CHANNEL)
+        );
+        write_reg!(ral::flexio, flexio, CTRL, FLEXEN: 1);
+
+        Self {
+            pending_channels: 0,
+            pending_data_bits: 0,
+        }
+    }
+
+    /// Whether the last step is still waiting for clock pulses to finish.
+    pub fn pulses_pending(&self) -> bool {
+        self.pending_channels != 0
+    }
+
+    /// Called from the FlexIO1 interrupt. Clears the finished pulses, and writes the held back data
+    /// lines once all of them are over. Returns whether the next step can be taken.
+    pub fn finish_pulses(&mut self) -> bool {
+        let flexio = peripherals::flexio1();
+        let finished_channels = read_reg!(ral::flexio, flexio, TIMSTAT);
+        write_reg!(ral::flexio, flexio, TIMSTAT, finished_channels);
+
+        self.pending_channels &= !finished_channels;
+        if self.pending_channels != 0 {
+            return false;
+        }
+
+        write_reg!(ral::gpio, peripherals::gpio6(), DR, self.pending_data_bits);
+        true
+    }
+}
+
+fn setup_pulse_channel(channel: usize, flexio_pin: u8) {
+    let flexio = peripherals::flexio1();
+
+    // transmit mode, without an output pin
+    write_reg!(
+        ral::flexio,
+        flexio,
+        SHIFTCTL[channel],
+        TIMSEL: channel as u32,
+        PINCFG: 0,
+        SMOD: 0b010
+    );
+    write_reg!(ral::flexio, flexio, SHIFTCFG[channel], 0);
+
+    write_reg!(ral::flexio, flexio, TIMCMP[channel], PULSE_COMPARE);
+    // enabled while the shifter buffer is full, disabled once the pulse is over
+    write_reg!(
+        ral::flexio,
+        flexio,
+        TIMCFG[channel],
+        TIMOUT: 1,
+        TIMDEC: 0,
+        TIMRST: 0,
+        TIMDIS: 0b010,
+        TIMENA: 0b010,
+        TSTOP: 0,
+        TSTART: 0
+    );
+    // dual 8-bit baud mode, triggered by the inverted status flag of the shifter
+    write_reg!(
+        ral::flexio,
+        flexio,
+        TIMCTL[channel],
+        TRGSEL: (channel as u32) * 4 + 1,
+        TRGPOL: 1,
+        TRGSRC: 1,
+        PINCFG: 0b11,
+        PINSEL: flexio_pin as u32,
+        PINPOL: 0,
+        TIMOD: 0b01
+    );
+}
+
+impl OutputSink for FlexIoOutput {
+    fn clock_on(&mut self, clock_bits: u32) {
+        let flexio = peripherals::flexio1();
+
+        // when both clocks are pulsed, the latch has to be started first so the storage register
+        // never receives the contents from after the shift
+        if clock_bits & (0b1 << P2::OFFSET) != 0 {
+            write_reg!(ral::flexio, flexio, SHIFTBUF[LATCH_CLOCK_CHANNEL], 0);
+            self.pending_channels |= 0b1 << LATCH_CLOCK_CHANNEL;
+        }
+        if clock_bits & (0b1 << P3::OFFSET) != 0 {
+            write_reg!(ral::flexio, flexio, SHIFTBUF[SHIFT_CLOCK_CHANNEL], 0);
+            self.pending_channels |= 0b1 << SHIFT_CLOCK_CHANNEL;
+        }
+    }
+
+    fn clock_off(&mut self, _clock_bits: u32) {
+        // the pulses end by themselves
+    }
+
+    fn data_out(&mut self, data_bits: u32) {
+        // the data lines can't change before the pulses are over
+        if self.pending_channels == 0 {
+            write_reg!(ral::gpio, peripherals::gpio6(), DR, data_bits);
+        } else {
+            self.pending_data_bits = data_bits;
+        }
+    }
+
+    fn wait_cycles<const CYCLES: u64>(&mut self) {
+        // the pulse width is timed by FlexIO
+    }
+
+    fn rtc_value(&mut self) -> u32 {
+        read_rtc()
+    }
+}
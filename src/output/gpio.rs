@@ -0,0 +1,84 @@
+use core::hint::spin_loop;
+
+use teensy4_bsp::hal::ccm::clock_gate;
+use teensy4_bsp::hal::iomuxc::gpio::Pin;
+use teensy4_bsp::pins::t40::{ErasedPins, P2, P3};
+use teensy4_bsp::ral;
+use teensy4_bsp::ral::{modify_reg, read_reg, write_reg};
+
+use super::OutputSink;
+use crate::intrinsics::yield_cycles;
+use crate::peripherals;
+use crate::pins::*;
+
+/// Drives the shift registers through the high-speed GPIO6 and GPIO9 ports.
+pub struct GpioOutput {
+    _private: (),
+}
+
+impl GpioOutput {
+    pub fn new(erased_pins: &mut ErasedPins, column_count: usize) -> Self {
+        setup_output_pins(erased_pins, column_count);
+        enable_rtc();
+
+        Self { _private: () }
+    }
+}
+
+pub(super) fn setup_output_pins(erased_pins: &mut ErasedPins, column_count: usize) {
+    unsafe {
+        // configure LED output pins
+        for (&idx, &bit_offset) in LED_OUTPUT_PIN_INDICES
+            .iter()
+            .zip(GPIO6_BATCHED_PIN_OFFSETS.flatten().iter())
+            .take(column_count)
+        {
+            led_output_pin_setup(
+                erased_pins.get_mut(idx as usize).unwrap_unchecked(),
+                bit_offset,
+            );
+        }
+
+        // configure clock pins
+        clock_pin_setup(erased_pins.get_mut(2).unwrap_unchecked(), P2::OFFSET);
+        clock_pin_setup(erased_pins.get_mut(3).unwrap_unchecked(), P3::OFFSET);
+    }
+}
+
+pub(super) fn enable_rtc() {
+    // enable SNVS HP clock gate because the RTC is on it
+    clock_gate::snvs_hp().set(&mut peripherals::ccm(), clock_gate::ON);
+
+    // enable RTC and wait for it to get set
+    let snvs = peripherals::snvs();
+    modify_reg!(ral::snvs, snvs, HPCR, RTC_EN: RTC_EN_1);
+    while read_reg!(ral::snvs, snvs, HPCR, RTC_EN != RTC_EN_1) {
+        spin_loop();
+    }
+}
+
+pub(super) fn read_rtc() -> u32 {
+    read_reg!(ral::snvs, peripherals::snvs(), HPRTCLR)
+}
+
+impl OutputSink for GpioOutput {
+    fn clock_on(&mut self, clock_bits: u32) {
+        write_reg!(ral::gpio, peripherals::gpio9(), DR_SET, clock_bits);
+    }
+
+    fn clock_off(&mut self, clock_bits: u32) {
+        write_reg!(ral::gpio, peripherals::gpio9(), DR_CLEAR, clock_bits);
+    }
+
+    fn data_out(&mut self, data_bits: u32) {
+        write_reg!(ral::gpio, peripherals::gpio6(), DR, data_bits);
+    }
+
+    fn wait_cycles<const CYCLES: u64>(&mut self) {
+        yield_cycles::<CYCLES>();
+    }
+
+    fn rtc_value(&mut self) -> u32 {
+        read_rtc()
+    }
+}
@@ -0,0 +1,41 @@
+#[cfg(target_arch = "arm")]
+mod dma;
+#[cfg(target_arch = "arm")]
+mod flexio;
+#[cfg(target_arch = "arm")]
+mod gpio;
+#[cfg(not(target_arch = "arm"))]
+mod simulation;
+mod toggle_stream;
+
+#[cfg(target_arch = "arm")]
+pub use dma::{DmaOutput, DMA_STEPS_PER_HALF};
+#[cfg(target_arch = "arm")]
+pub use flexio::FlexIoOutput;
+#[cfg(target_arch = "arm")]
+pub use gpio::GpioOutput;
+#[cfg(not(target_arch = "arm"))]
+pub use simulation::{OutputEvent, SimulatedLowPower, SimulatedOutput, SimulatedScreen};
+
+// Everything the screen driver needs from the outside world goes through this trait, so the
+// shift-out logic can be run against real pins or against a recording on the host.
+pub trait OutputSink {
+    /// Raises the given clock lines (SRCLK/RCLK bits, in GPIO9 bit positions).
+    fn clock_on(&mut self, clock_bits: u32);
+
+    /// Lowers the given clock lines (SRCLK/RCLK bits, in GPIO9 bit positions).
+    fn clock_off(&mut self, clock_bits: u32);
+
+    /// Writes the serial data lines of every column at once (in GPIO6 bit positions).
+    fn data_out(&mut self, data_bits: u32);
+
+    /// Waits for at least the given amount of CPU cycles.
+    fn wait_cycles<const CYCLES: u64>(&mut self);
+
+    /// Called for steps that leave every line as it is. Sinks that stream precomputed words have
+    /// to fill the step in to keep the timing.
+    fn hold(&mut self) {}
+
+    /// The low word of the 32768 Hz real time counter.
+    fn rtc_value(&mut self) -> u32;
+}
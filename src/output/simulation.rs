@@ -0,0 +1,507 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use super::OutputSink;
+use crate::color::AdjustedColor;
+use crate::framebuffer::{
+    build_check, ColorLines, FrameHandoff, Framebuffer, PANEL_HEIGHT, PANEL_WIDTH,
+};
+use crate::led_driver::{
+    FrameRate, Modulation, ScreenDriver, ShiftEngine, ARM_FREQUENCY, RTC_FREQUENCY,
+};
+use crate::low_power::{LowPowerDomain, LOW_POWER_REGISTERS};
+use crate::pins::{GPIO6_BATCHED_PIN_OFFSETS, LATCH_CLOCK_OFFSET, SHIFT_CLOCK_OFFSET};
+use crate::program::Program;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum OutputEvent {
+    ClockOn { cycle: u64, clock_bits: u32 },
+    ClockOff { cycle: u64, clock_bits: u32 },
+    DataOut { cycle: u64, data_bits: u32 },
+}
+
+/// An output sink that doesn't touch any hardware. Every write is recorded, and the shift
+/// registers are modelled so the image that the matrix would show can be rebuilt afterwards.
+///
+/// Time is virtual: it only advances when the engine waits, when an output is written, or when
+/// [`SimulatedOutput::advance_cycles`] is called, so runs are fully deterministic. Whatever steps
+/// the engine should advance the time by the step period, like the refresh timer would.
+pub struct SimulatedOutput<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    cycles: u64,
+    clock_bits: u32,
+    data_bits: u32,
+
+    // bit N of each column holds the value that has been shifted N times
+    shift_registers: [u32; WIDTH],
+    latched_registers: [u32; WIDTH],
+    last_latch_cycle: u64,
+    latch_count: u32,
+
+    lit_cycles: [[[u64; WIDTH]; ColorLines::COUNT]; HEIGHT],
+    total_cycles: u64,
+
+    events: Vec<OutputEvent>,
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> SimulatedOutput<WIDTH, HEIGHT> {
+    // rough cost of a single register write, so the virtual clock keeps moving during a frame
+    pub const CYCLES_PER_WRITE: u64 = 2;
+
+    pub const SHIFT_CLOCK_BIT: u32 = 0b1 << SHIFT_CLOCK_OFFSET;
+    pub const LATCH_CLOCK_BIT: u32 = 0b1 << LATCH_CLOCK_OFFSET;
+
+    const SHIFT_COUNT: usize = HEIGHT * ColorLines::COUNT;
+    const REGISTER_CHECK: () = assert!(
+        Self::SHIFT_COUNT <= u32::BITS as usize,
+        "the simulated shift registers can't hold that many lines"
+    );
+
+    pub fn new() -> Self {
+        build_check(Framebuffer::<WIDTH, HEIGHT>::GEOMETRY_CHECK);
+        build_check(Self::REGISTER_CHECK);
+
+        Self {
+            cycles: 0,
+            clock_bits: 0,
+            data_bits: 0,
+            shift_registers: [0; WIDTH],
+            latched_registers: [0; WIDTH],
+            last_latch_cycle: 0,
+            latch_count: 0,
+            lit_cycles: [[[0; WIDTH]; ColorLines::COUNT]; HEIGHT],
+            total_cycles: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn advance_cycles(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// How many times the storage registers have been latched, which keeps counting across clears.
+    pub fn latch_count(&self) -> u32 {
+        self.latch_count
+    }
+
+    pub fn events(&self) -> &[OutputEvent] {
+        &self.events
+    }
+
+    /// Forgets all recorded events and accumulated brightness, but keeps the register contents.
+    /// The lines that are latched right now still get counted from the time they were latched, so
+    /// clearing after a latch measures whole latch periods.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.lit_cycles = [[[0; WIDTH]; ColorLines::COUNT]; HEIGHT];
+        self.total_cycles = 0;
+    }
+
+    /// Whether the LED on the given shift line is currently being driven by the latched outputs.
+    pub fn is_lit(&self, led_x: usize, line: usize) -> bool {
+        (self.latched_registers[led_x] >> (Self::SHIFT_COUNT - 1 - line)) & 0b1 != 0
+    }
+
+    /// Rebuilds the colors shown by the matrix since the last clear, by averaging the time each
+    /// LED spent lit.
+    pub fn rendered_frame(&self) -> [[AdjustedColor; WIDTH]; HEIGHT] {
+        let mut frame = [[AdjustedColor::default(); WIDTH]; HEIGHT];
+
+        if self.total_cycles == 0 {
+            return frame;
+        }
+
+        for (line, lit_cycles_line) in self.lit_cycles.flatten().iter().enumerate() {
+            for (x, &lit_cycles) in lit_cycles_line.iter().enumerate() {
+                // rounded to the nearest value
+                let value = ((lit_cycles * u8::MAX as u64 + self.total_cycles / 2)
+                    / self.total_cycles) as u8;
+                let color = &mut frame[line / ColorLines::COUNT][x];
+
+                match line % ColorLines::COUNT {
+                    0 => color.r = value,
+                    1 => color.g = value,
+                    _ => color.b = value,
+                }
+            }
+        }
+
+        frame
+    }
+
+    fn record(&mut self, event: OutputEvent) {
+        self.events.push(event);
+        self.cycles += Self::CYCLES_PER_WRITE;
+    }
+
+    fn latch(&mut self) {
+        let elapsed = self.cycles - self.last_latch_cycle;
+
+        for (line, lit_cycles_line) in self.lit_cycles.flatten_mut().iter_mut().enumerate() {
+            for (x, lit_cycles) in lit_cycles_line.iter_mut().enumerate() {
+                if (self.latched_registers[x] >> (Self::SHIFT_COUNT - 1 - line)) & 0b1 != 0 {
+                    *lit_cycles += elapsed;
+                }
+            }
+        }
+
+        self.total_cycles += elapsed;
+        self.last_latch_cycle = self.cycles;
+        self.latch_count = self.latch_count.wrapping_add(1);
+        self.latched_registers = self.shift_registers;
+    }
+
+    fn shift(&mut self) {
+        for (register, &bit_offset) in self
+            .shift_registers
+            .iter_mut()
+            .zip(GPIO6_BATCHED_PIN_OFFSETS.flatten().iter())
+        {
+            *register = (*register << 1) | ((self.data_bits >> bit_offset) & 0b1);
+        }
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Default for SimulatedOutput<WIDTH, HEIGHT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> OutputSink for SimulatedOutput<WIDTH, HEIGHT> {
+    fn clock_on(&mut self, clock_bits: u32) {
+        self.record(OutputEvent::ClockOn {
+            cycle: self.cycles,
+            clock_bits,
+        });
+
+        let rising_bits = clock_bits & !self.clock_bits;
+        self.clock_bits |= clock_bits;
+
+        // when both clocks rise together, the storage register receives the contents from before
+        // the shift, so the latch has to be modelled first
+        if rising_bits & Self::LATCH_CLOCK_BIT != 0 {
+            self.latch();
+        }
+        if rising_bits & Self::SHIFT_CLOCK_BIT != 0 {
+            self.shift();
+        }
+    }
+
+    fn clock_off(&mut self, clock_bits: u32) {
+        self.record(OutputEvent::ClockOff {
+            cycle: self.cycles,
+            clock_bits,
+        });
+
+        self.clock_bits &= !clock_bits;
+    }
+
+    fn data_out(&mut self, data_bits: u32) {
+        self.record(OutputEvent::DataOut {
+            cycle: self.cycles,
+            data_bits,
+        });
+
+        self.data_bits = data_bits;
+    }
+
+    fn wait_cycles<const CYCLES: u64>(&mut self) {
+        self.cycles += CYCLES;
+    }
+
+    fn rtc_value(&mut self) -> u32 {
+        ((self.cycles * RTC_FREQUENCY as u64) / ARM_FREQUENCY as u64) as u32
+    }
+}
+
+#[derive(Default)]
+struct LowPowerState {
+    micros: u64,
+    registers: [u32; LOW_POWER_REGISTERS],
+}
+
+std::thread_local! {
+    static LOW_POWER_STATE: RefCell<LowPowerState> = RefCell::new(LowPowerState::default());
+}
+
+/// The SNVS low power domain, kept in memory. Every handle on a thread shares the same state, like
+/// every handle on the Teensy reads the same registers, so tests can set the time that a program
+/// goes by. Each test runs on a thread of its own, and starts out at 1970 with cleared registers.
+///
+/// The time only moves when [`SimulatedLowPower::advance_micros`] is called.
+pub struct SimulatedLowPower;
+
+impl SimulatedLowPower {
+    pub fn advance_micros(&mut self, micros: u64) {
+        LOW_POWER_STATE.with_borrow_mut(|state| state.micros += micros);
+    }
+}
+
+impl LowPowerDomain for SimulatedLowPower {
+    fn open() -> Self {
+        Self
+    }
+
+    fn time_with_micros(&self) -> (u32, u32) {
+        let micros = LOW_POWER_STATE.with_borrow(|state| state.micros);
+
+        ((micros / 1_000_000) as u32, (micros % 1_000_000) as u32)
+    }
+
+    fn set_time(&mut self, seconds: u32) {
+        LOW_POWER_STATE.with_borrow_mut(|state| state.micros = seconds as u64 * 1_000_000);
+    }
+
+    fn register(&self, index: usize) -> u32 {
+        LOW_POWER_STATE.with_borrow(|state| state.registers[index])
+    }
+
+    fn set_register(&mut self, index: usize, value: u32) {
+        LOW_POWER_STATE.with_borrow_mut(|state| state.registers[index] = value);
+    }
+}
+
+/// Runs programs through the whole driver. Frames go through a handoff like they do on the
+/// Teensy, and the shift engine gets stepped against a [`SimulatedOutput`] in place of the refresh
+/// interrupt.
+pub struct SimulatedScreen<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    pub driver: ScreenDriver<WIDTH, HEIGHT>,
+    pub engine: ShiftEngine<SimulatedOutput<WIDTH, HEIGHT>, WIDTH, HEIGHT>,
+    handoff: &'static FrameHandoff<WIDTH, HEIGHT>,
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> SimulatedScreen<WIDTH, HEIGHT> {
+    // the refresh timer runs a shift cycle every time, with a microsecond for each step
+    pub const SHIFT_CYCLE_PERIOD_CYCLES: u64 =
+        (ARM_FREQUENCY as u64 / 1_000_000) * (HEIGHT * ColorLines::COUNT) as u64;
+
+    pub fn new(modulation: Modulation) -> Self {
+        // the driver and the engine need a handoff that outlives them, like the static one
+        let handoff: &'static FrameHandoff<WIDTH, HEIGHT> =
+            Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps64.rtc_mask())));
+
+        Self {
+            driver: ScreenDriver::with_handoff(handoff),
+            engine: ShiftEngine::new(SimulatedOutput::new(), modulation, handoff),
+            handoff,
+        }
+    }
+
+    pub fn output(&self) -> &SimulatedOutput<WIDTH, HEIGHT> {
+        self.engine.output()
+    }
+
+    /// Runs the engine for a shift cycle like the refresh interrupt does, and lets the rest of the
+    /// timer period pass.
+    pub fn step_shift_cycle(&mut self) {
+        let start = self.engine.output().cycles();
+        self.engine.step_shift_cycle();
+
+        let elapsed = self.engine.output().cycles() - start;
+        self.engine
+            .output_mut()
+            .advance_cycles(Self::SHIFT_CYCLE_PERIOD_CYCLES.saturating_sub(elapsed));
+    }
+
+    /// Renders a frame of the program, and returns what the matrix shows once the frame is up.
+    pub fn render_frame(
+        &mut self,
+        program: &mut dyn Program<WIDTH, HEIGHT>,
+    ) -> [[AdjustedColor; WIDTH]; HEIGHT] {
+        program.render(&mut self.driver);
+        self.show_frame()
+    }
+
+    /// Flips the back buffer, and returns what the matrix shows over a whole modulation period
+    /// once the frame is up. Frame pacing is skipped, so the frame gets picked up right away.
+    pub fn show_frame(&mut self) -> [[AdjustedColor; WIDTH]; HEIGHT] {
+        // programs may have flipped a frame on their own
+        self.run_until_taken();
+        self.driver.framebuffer.flip();
+        self.run_until_taken();
+
+        // whatever was latched before the frame got picked up is gone after a whole period
+        let period_shift_cycles = self.engine.modulation_period_steps()
+            / ShiftEngine::<SimulatedOutput<WIDTH, HEIGHT>, WIDTH, HEIGHT>::SHIFT_COUNT;
+        self.run_shift_cycles(period_shift_cycles);
+        self.run_until_latched();
+
+        // the period ends with the shift cycle that latches again, and the time the lines were
+        // shown for is only counted at that latch
+        self.engine.output_mut().clear();
+        self.run_shift_cycles(period_shift_cycles - 1);
+        self.run_until_latched();
+
+        self.engine.output().rendered_frame()
+    }
+
+    fn run_shift_cycles(&mut self, shift_cycles: u32) {
+        for _ in 0..shift_cycles {
+            self.step_shift_cycle();
+        }
+    }
+
+    fn run_until_latched(&mut self) {
+        let latch_count = self.engine.output().latch_count();
+        while self.engine.output().latch_count() == latch_count {
+            self.step_shift_cycle();
+        }
+    }
+
+    fn run_until_taken(&mut self) {
+        let rtc_mask = self.handoff.rtc_mask();
+        let frame_period = self.handoff.frame_period();
+
+        // every tick of the RTC counts as a new frame
+        self.handoff.set_rtc_mask(FrameRate::Fps32768.rtc_mask());
+        self.handoff.set_frame_period(0);
+        while self.handoff.is_full() {
+            self.step_shift_cycle();
+        }
+
+        self.handoff.set_rtc_mask(rtc_mask);
+        self.handoff.set_frame_period(frame_period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::WHITE;
+    use crate::framebuffer::FRAME_PERIOD_FRACTION_BITS;
+    use crate::program::{HueCycle, WhiteBalance, PROGRAM_CONSTRUCTORS};
+
+    // the modulation can only get within a step of the wanted value
+    fn assert_shows_back_buffer(
+        screen: &SimulatedScreen,
+        frame: &[[AdjustedColor; PANEL_WIDTH]; PANEL_HEIGHT],
+    ) {
+        let back_buffer = &screen.driver.framebuffer.back_buffer;
+
+        for (y, row) in frame.iter().enumerate() {
+            for (x, shown) in row.iter().enumerate() {
+                let drawn = back_buffer.get_led_adjusted(x, y);
+                for (shown, drawn) in [(shown.r, drawn.r), (shown.g, drawn.g), (shown.b, drawn.b)] {
+                    assert!(
+                        shown.abs_diff(drawn) <= 1,
+                        "LED ({x}, {y}) shows {shown} instead of {drawn}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_program_shows_what_it_drew() {
+        for constructor in PROGRAM_CONSTRUCTORS {
+            let mut screen: SimulatedScreen = SimulatedScreen::new(Modulation::Accumulator);
+            let mut program = constructor(&mut screen.driver);
+
+            for _ in 0..3 {
+                let frame = screen.render_frame(program.as_mut());
+                assert_shows_back_buffer(&screen, &frame);
+            }
+        }
+    }
+
+    #[test]
+    fn bit_planes_show_what_was_drawn() {
+        let mut screen: SimulatedScreen = SimulatedScreen::new(Modulation::BitPlane);
+        let mut program = HueCycle::new(&mut screen.driver);
+
+        let frame = screen.render_frame(program.as_mut());
+        assert_shows_back_buffer(&screen, &frame);
+    }
+
+    #[test]
+    fn white_balance_pattern() {
+        let mut screen: SimulatedScreen = SimulatedScreen::new(Modulation::Accumulator);
+        let mut program = WhiteBalance::new(&mut screen.driver);
+        let frame = screen.render_frame(program.as_mut());
+
+        // the bottom row shows the channels on their own, red first
+        let red = frame[PANEL_HEIGHT - 1][0];
+        assert!(red.r > 0 && red.g == 0 && red.b == 0);
+        let blue = frame[PANEL_HEIGHT - 1][PANEL_WIDTH - 1];
+        assert!(blue.r == 0 && blue.g == 0 && blue.b > 0);
+
+        // the top row is a ramp that ends at white
+        let white = frame[0][PANEL_WIDTH - 1];
+        let expected = WHITE.adjust_for_led();
+        assert!(
+            white.r.abs_diff(expected.r) <= 1
+                && white.g.abs_diff(expected.g) <= 1
+                && white.b.abs_diff(expected.b) <= 1
+        );
+    }
+
+    #[test]
+    fn brightness_dims_without_turning_pixels_off() {
+        let mut screen: SimulatedScreen = SimulatedScreen::new(Modulation::Accumulator);
+        let mut program = WhiteBalance::new(&mut screen.driver);
+        screen.driver.set_brightness(1);
+
+        let frame = screen.render_frame(program.as_mut());
+        assert!(frame
+            .flatten()
+            .iter()
+            .all(|color| color.r | color.g | color.b != 0));
+    }
+
+    // keeps a frame waiting in the handoff, and counts how many the engine picks up while the RTC
+    // runs for the given amount of ticks
+    fn count_paced_frames(screen: &mut SimulatedScreen, rtc_ticks: u32) -> u32 {
+        let start = screen.engine.output_mut().rtc_value();
+        let mut frames = 0;
+
+        while screen.engine.output_mut().rtc_value().wrapping_sub(start) < rtc_ticks {
+            if !screen.handoff.is_full() {
+                screen.driver.framebuffer.flip();
+            }
+            screen.step_shift_cycle();
+
+            if !screen.handoff.is_full() {
+                frames += 1;
+            }
+        }
+
+        frames
+    }
+
+    #[test]
+    fn frame_rates_that_dont_divide_the_rtc_are_kept() {
+        const FRAME_RATE_HZ: u32 = 30;
+
+        let mut screen: SimulatedScreen = SimulatedScreen::new(Modulation::Accumulator);
+        screen.driver.set_frame_rate_hz(FRAME_RATE_HZ);
+
+        // the first deadline is left over from before the rate was set
+        count_paced_frames(&mut screen, RTC_FREQUENCY / 4);
+        let frames = count_paced_frames(&mut screen, RTC_FREQUENCY);
+        assert!(
+            frames.abs_diff(FRAME_RATE_HZ) <= 1,
+            "{frames} frames in a second"
+        );
+
+        // the deadlines are in fixed point RTC ticks, so they wrap around every 2^20 ticks
+        let wrap_ticks = 1_u64 << (u32::BITS - FRAME_PERIOD_FRACTION_BITS);
+        let rtc_now = screen.engine.output_mut().rtc_value() as u64;
+        let ticks_to_skip = wrap_ticks - rtc_now % wrap_ticks - RTC_FREQUENCY as u64 / 2;
+        screen
+            .engine
+            .output_mut()
+            .advance_cycles(ticks_to_skip * ARM_FREQUENCY as u64 / RTC_FREQUENCY as u64);
+
+        count_paced_frames(&mut screen, RTC_FREQUENCY / 4);
+        let frames = count_paced_frames(&mut screen, RTC_FREQUENCY);
+        assert!(
+            frames.abs_diff(FRAME_RATE_HZ) <= 1,
+            "{frames} frames in a second across the wraparound"
+        );
+    }
+}
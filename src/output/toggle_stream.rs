@@ -0,0 +1,219 @@
+// The words that the DMA output streams go to DR_TOGGLE, so every tick only holds the lines that
+// change from the tick before it. The stream loops over two halves, and a half gets refilled while
+// the other one is being sent, so the tick before the start of a half is the last tick of the other
+// half, and not whatever happened to be written last. This is kept apart from the DMA registers so
+// it can be checked on the host.
+
+/// A tick to write into the stream buffers.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Tick {
+    /// Where the tick goes in the buffers.
+    pub slot: usize,
+    pub data_word: u32,
+    pub clock_word: u32,
+}
+
+/// Turns line levels into toggle words for a stream of `TICKS` ticks, which is sent in a loop.
+pub struct ToggleStream<const TICKS: usize> {
+    /// The data lines from the next tick on.
+    pub data_bits: u32,
+    /// The clock lines from the next tick on.
+    pub clock_bits: u32,
+    // the lines as of the last written tick, which the next tick toggles from
+    written_data_bits: u32,
+    written_clock_bits: u32,
+    // the lines as of the end of each half, which the other half toggles from
+    half_end_bits: [(u32, u32); 2],
+    cursor: usize,
+}
+
+impl<const TICKS: usize> ToggleStream<TICKS> {
+    pub const HALF_TICKS: usize = TICKS / 2;
+
+    /// Every line has to be low before the stream starts.
+    pub const fn new() -> Self {
+        Self {
+            data_bits: 0,
+            clock_bits: 0,
+            written_data_bits: 0,
+            written_clock_bits: 0,
+            half_end_bits: [(0, 0); 2],
+            cursor: 0,
+        }
+    }
+
+    /// Moves the cursor to the start of the given half, which toggles from the lines as of the end
+    /// of the other half. Ticks that were written before the first seek don't count, since the
+    /// stream starts out with every line low.
+    pub fn seek(&mut self, half: usize) {
+        (self.written_data_bits, self.written_clock_bits) = self.half_end_bits[1 - half];
+        self.cursor = half * Self::HALF_TICKS;
+    }
+
+    /// The words that take the lines from the previous tick to the current levels.
+    pub fn next_tick(&mut self) -> Tick {
+        debug_assert!(self.cursor < TICKS);
+
+        let tick = Tick {
+            slot: self.cursor,
+            data_word: self.data_bits ^ self.written_data_bits,
+            clock_word: self.clock_bits ^ self.written_clock_bits,
+        };
+        self.written_data_bits = self.data_bits;
+        self.written_clock_bits = self.clock_bits;
+        self.cursor += 1;
+
+        if self.cursor % Self::HALF_TICKS == 0 {
+            self.half_end_bits[self.cursor / Self::HALF_TICKS - 1] =
+                (self.written_data_bits, self.written_clock_bits);
+        }
+
+        tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::color::WHITE;
+    use crate::framebuffer::FrameHandoff;
+    use crate::led_driver::{FrameRate, Modulation, ScreenDriver, ShiftEngine};
+    use crate::output::OutputSink;
+
+    const STEPS_PER_HALF: usize = 7;
+    // every step is two ticks
+    const TICKS: usize = STEPS_PER_HALF * 2 * 2;
+
+    // the DMA output, with the buffers it would stream and the lines that every tick should leave
+    struct StreamOutput {
+        stream: ToggleStream<TICKS>,
+        data_words: [u32; TICKS],
+        clock_words: [u32; TICKS],
+        wanted_lines: [(u32, u32); TICKS],
+        rtc: u32,
+    }
+
+    impl StreamOutput {
+        fn new() -> Self {
+            Self {
+                stream: ToggleStream::new(),
+                data_words: [0; TICKS],
+                clock_words: [0; TICKS],
+                wanted_lines: [(0, 0); TICKS],
+                rtc: 0,
+            }
+        }
+
+        fn push_tick(&mut self) {
+            let tick = self.stream.next_tick();
+            self.data_words[tick.slot] = tick.data_word;
+            self.clock_words[tick.slot] = tick.clock_word;
+            self.wanted_lines[tick.slot] = (self.stream.data_bits, self.stream.clock_bits);
+        }
+    }
+
+    impl OutputSink for StreamOutput {
+        fn clock_on(&mut self, clock_bits: u32) {
+            self.stream.clock_bits |= clock_bits;
+            self.push_tick();
+        }
+
+        fn clock_off(&mut self, clock_bits: u32) {
+            self.stream.clock_bits &= !clock_bits;
+        }
+
+        fn data_out(&mut self, data_bits: u32) {
+            self.stream.data_bits = data_bits;
+            self.push_tick();
+        }
+
+        fn wait_cycles<const CYCLES: u64>(&mut self) {}
+
+        fn hold(&mut self) {
+            self.push_tick();
+            self.push_tick();
+        }
+
+        fn rtc_value(&mut self) -> u32 {
+            self.rtc += 1;
+            self.rtc
+        }
+    }
+
+    // toggles the lines like the DMA does while sending a half, and checks that every tick leaves
+    // them where they were wanted. Returns whether any data line was high
+    fn send_half(output: &StreamOutput, half: usize, lines: &mut (u32, u32)) -> bool {
+        let mut data_seen = false;
+
+        for slot in half * (TICKS / 2)..(half + 1) * (TICKS / 2) {
+            lines.0 ^= output.data_words[slot];
+            lines.1 ^= output.clock_words[slot];
+            assert_eq!(
+                *lines, output.wanted_lines[slot],
+                "tick {slot} is out of sync"
+            );
+            data_seen |= lines.0 != 0;
+        }
+
+        data_seen
+    }
+
+    #[test]
+    fn ticks_before_the_first_seek_are_dropped() {
+        let mut output = StreamOutput::new();
+        // like the first data word of an engine that starts with a frame up
+        output.data_out(0b1011);
+
+        let mut lines = (0, 0);
+        for half in 0..2 {
+            output.stream.seek(half);
+            for step in 0..STEPS_PER_HALF as u32 {
+                output.clock_on(0b1);
+                output.clock_off(0b1);
+                output.data_out(step * 0b101);
+            }
+        }
+        for half in 0..2 {
+            send_half(&output, half, &mut lines);
+        }
+    }
+
+    #[test]
+    fn stream_stays_in_sync_from_a_lit_frame() {
+        let handoff = Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps32768.rtc_mask())));
+        let mut driver: ScreenDriver = ScreenDriver::with_handoff(handoff);
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        let (width, height) = (back_buffer.width() as i32, back_buffer.height() as i32);
+        back_buffer.fill_rect(0, 0, width, height, WHITE.adjust_for_led());
+        driver.framebuffer.flip();
+
+        let mut engine: ShiftEngine<StreamOutput> =
+            ShiftEngine::new(StreamOutput::new(), Modulation::Accumulator, handoff);
+
+        // both halves get filled before the stream starts, and every half that has been sent gets
+        // refilled while the other one is being sent
+        for half in 0..2 {
+            engine.output_mut().stream.seek(half);
+            for _ in 0..STEPS_PER_HALF {
+                engine.step();
+            }
+        }
+
+        let mut lines = (0, 0);
+        let mut data_seen = false;
+        for _ in 0..200 {
+            for half in 0..2 {
+                data_seen |= send_half(engine.output(), half, &mut lines);
+
+                engine.output_mut().stream.seek(half);
+                for _ in 0..STEPS_PER_HALF {
+                    engine.step();
+                }
+            }
+        }
+
+        assert!(data_seen, "the frame never showed up on the data lines");
+    }
+}
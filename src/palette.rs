@@ -0,0 +1,263 @@
+use core::ops::Range;
+
+use crate::color::{AdjustedColor, Color, BLACK};
+use crate::framebuffer::{build_check, BackBuffer, PANEL_HEIGHT, PANEL_WIDTH};
+
+// Indexed color, for effects that are easier to express as levels than as colors, like fire or
+// plasma. The canvas only stores palette indices, and the colors get looked up when it is expanded
+// into the back buffer. Rotating palette entries animates the whole frame without touching any
+// pixels.
+
+/// Up to 256 colors, already adjusted for the LEDs.
+#[derive(Copy, Clone)]
+pub struct Palette<const SIZE: usize> {
+    colors: [AdjustedColor; SIZE],
+}
+
+impl<const SIZE: usize> Palette<SIZE> {
+    const SIZE_CHECK: () = assert!(
+        SIZE > 0 && SIZE <= u8::MAX as usize + 1,
+        "palettes have to be indexable by a u8"
+    );
+
+    pub const fn new(colors: [AdjustedColor; SIZE]) -> Self {
+        build_check(Self::SIZE_CHECK);
+
+        Self { colors }
+    }
+
+    pub const fn from_colors(colors: [Color; SIZE]) -> Self {
+        let mut adjusted = [AdjustedColor { r: 0, g: 0, b: 0 }; SIZE];
+
+        let mut i = 0;
+        while i < SIZE {
+            adjusted[i] = colors[i].adjust_for_led();
+            i += 1;
+        }
+
+        Self::new(adjusted)
+    }
+
+    /// Spreads the stops evenly over the palette, and blends between them. The first entry gets
+    /// the first stop, and the last entry gets the last one.
+    pub const fn gradient(stops: &[Color]) -> Self {
+        assert!(!stops.is_empty(), "a gradient needs at least one stop");
+
+        let mut colors = [Color { r: 0, g: 0, b: 0 }; SIZE];
+        let segments = (stops.len() - 1) as u32;
+        let last_entry = if SIZE > 1 { (SIZE - 1) as u32 } else { 1 };
+
+        let mut i = 0;
+        while i < SIZE {
+            // the position along the stops, in 1/last_entry steps
+            let position = i as u32 * segments;
+            let segment = position / last_entry;
+
+            colors[i] = if segment >= segments {
+                stops[stops.len() - 1]
+            } else {
+                let weight = position % last_entry;
+                let from = stops[segment as usize];
+                let to = stops[segment as usize + 1];
+
+                Color {
+                    r: lerp(from.r, to.r, weight, last_entry),
+                    g: lerp(from.g, to.g, weight, last_entry),
+                    b: lerp(from.b, to.b, weight, last_entry),
+                }
+            };
+
+            i += 1;
+        }
+
+        Self::from_colors(colors)
+    }
+
+    /// Indices past the end of the palette wrap around.
+    pub fn color(&self, index: u8) -> AdjustedColor {
+        self.colors[index as usize % SIZE]
+    }
+
+    pub fn set_color(&mut self, index: u8, color: impl Into<AdjustedColor>) {
+        self.colors[index as usize % SIZE] = color.into();
+    }
+
+    /// Moves every entry in the range up by the amount of steps, and the ones that fall off the
+    /// end come back in at the start. Negative steps go the other way.
+    pub fn cycle(&mut self, range: Range<usize>, steps: i32) {
+        let entries = &mut self.colors[range];
+        if entries.is_empty() {
+            return;
+        }
+
+        let steps = steps.rem_euclid(entries.len() as i32) as usize;
+        entries.rotate_right(steps);
+    }
+}
+
+const fn lerp(from: u8, to: u8, weight: u32, total: u32) -> u8 {
+    ((from as u32 * (total - weight) + to as u32 * weight + total / 2) / total) as u8
+}
+
+/// A canvas of palette indices, which gets expanded into colors on the back buffer.
+///
+/// The canvas starts out the size of the panel, and can take the logical size of a turned back
+/// buffer instead, since that holds the same amount of pixels.
+pub struct IndexedCanvas<
+    const PALETTE_SIZE: usize,
+    const WIDTH: usize = PANEL_WIDTH,
+    const HEIGHT: usize = PANEL_HEIGHT,
+> {
+    pub palette: Palette<PALETTE_SIZE>,
+    // laid out row after row in the current size, which doesn't have to line up with the arrays
+    indices: [[u8; WIDTH]; HEIGHT],
+    width: usize,
+    height: usize,
+}
+
+impl<const PALETTE_SIZE: usize, const WIDTH: usize, const HEIGHT: usize>
+    IndexedCanvas<PALETTE_SIZE, WIDTH, HEIGHT>
+{
+    /// Every pixel starts out with the first palette entry.
+    pub const fn new(palette: Palette<PALETTE_SIZE>) -> Self {
+        Self {
+            palette,
+            indices: [[0; WIDTH]; HEIGHT],
+            width: WIDTH,
+            height: HEIGHT,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Takes the logical size of the back buffer. The pixels keep their order in memory, so they
+    /// should be drawn again if the size changed.
+    pub fn fit_to(&mut self, target: &BackBuffer<WIDTH, HEIGHT>) {
+        self.width = target.width();
+        self.height = target.height();
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> u8 {
+        let (column, row) = self.position(x, y);
+
+        self.indices[row][column]
+    }
+
+    pub fn set_index(&mut self, x: usize, y: usize, index: u8) {
+        let (column, row) = self.position(x, y);
+
+        self.indices[row][column] = index;
+    }
+
+    pub fn fill(&mut self, index: u8) {
+        self.indices = [[index; WIDTH]; HEIGHT];
+    }
+
+    /// Replaces the back buffer contents with the palette colors of every pixel. Pixels that
+    /// don't fit the logical size of the back buffer get clipped, and the parts of the back buffer
+    /// that the canvas doesn't cover get cleared.
+    pub fn expand_into(&self, target: &mut BackBuffer<WIDTH, HEIGHT>) {
+        for y in 0..target.height() {
+            for x in 0..target.width() {
+                let color = if x < self.width && y < self.height {
+                    self.palette.color(self.index(x, y))
+                } else {
+                    BLACK.adjust_for_led()
+                };
+                target.set_led_adjusted(x, y, color);
+            }
+        }
+    }
+
+    // where the pixel is in the arrays
+    fn position(&self, x: usize, y: usize) -> (usize, usize) {
+        debug_assert!(x < self.width);
+        debug_assert!(y < self.height);
+
+        let offset = y * self.width + x;
+
+        (offset % WIDTH, offset / WIDTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use super::*;
+    use crate::color::WHITE;
+    use crate::framebuffer::{FrameHandoff, Orientation, Rotation};
+    use crate::led_driver::{FrameRate, ScreenDriver};
+
+    #[test]
+    fn turned_canvas_covers_the_whole_back_buffer() {
+        let handoff = Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps64.rtc_mask())));
+        let mut driver: ScreenDriver = ScreenDriver::with_handoff(handoff);
+        driver.set_orientation(Orientation {
+            rotation: Rotation::Clockwise90,
+            mirror: false,
+        });
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        let (width, height) = (back_buffer.width(), back_buffer.height());
+        // left over from whatever was shown before
+        back_buffer.fill_rect(0, 0, width as i32, height as i32, WHITE.adjust_for_led());
+
+        let palette = Palette::<4>::from_colors([
+            Color::from_rgb(255, 0, 0),
+            Color::from_rgb(0, 255, 0),
+            Color::from_rgb(0, 0, 255),
+            Color::from_rgb(255, 255, 0),
+        ]);
+        let mut canvas: IndexedCanvas<4> = IndexedCanvas::new(palette);
+        canvas.fit_to(back_buffer);
+        assert_eq!((canvas.width(), canvas.height()), (width, height));
+
+        for y in 0..height {
+            for x in 0..width {
+                canvas.set_index(x, y, ((x + y * 3) % 4) as u8);
+            }
+        }
+        canvas.expand_into(back_buffer);
+
+        for y in 0..height {
+            for x in 0..width {
+                assert!(
+                    back_buffer.get_led_adjusted(x, y) == palette.color(((x + y * 3) % 4) as u8),
+                    "({x}, {y}) doesn't show its palette entry"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn back_buffer_outside_the_canvas_gets_cleared() {
+        let handoff = Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps64.rtc_mask())));
+        let mut driver: ScreenDriver = ScreenDriver::with_handoff(handoff);
+        driver.set_orientation(Orientation {
+            rotation: Rotation::Clockwise270,
+            mirror: false,
+        });
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        let (width, height) = (back_buffer.width(), back_buffer.height());
+        back_buffer.fill_rect(0, 0, width as i32, height as i32, WHITE.adjust_for_led());
+
+        // still the size of the unturned panel
+        let mut canvas: IndexedCanvas<1> = IndexedCanvas::new(Palette::new([WHITE.into()]));
+        canvas.fill(0);
+        canvas.expand_into(back_buffer);
+
+        for y in 0..height {
+            for x in 0..width {
+                let covered = x < canvas.width() && y < canvas.height();
+                let expected = if covered { WHITE } else { BLACK }.adjust_for_led();
+                assert!(back_buffer.get_led_adjusted(x, y) == expected);
+            }
+        }
+    }
+}
@@ -6,7 +6,8 @@ use teensy4_bsp::ral::*;
 // This module provides safe access to teensy board peripherals and cortex-m core
 // peripherals without codegen bloat, provided the following is upheld:
 // - CPU interrupts cannot change the execution flow, and there are no other cores
-//  on the machine.
+//  on the machine. The only exception is the refresh interrupt, which only writes to the
+//  GPIO6/GPIO9 data registers and reads the SNVS RTC, so it never races with the main code.
 
 // teensy board peripherals
 pub const fn adc1() -> adc::ADC1 {
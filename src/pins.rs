@@ -1,19 +1,66 @@
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::imxrt_iomuxc::gpio::Pin;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::imxrt_iomuxc::*;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::pins::t40::*;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::ral;
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::ral::modify_reg;
 
 use crate::intrinsics::BATCH_SIZE;
+#[cfg(target_arch = "arm")]
 use crate::peripherals;
 
-pub const LED_OUTPUT_PIN_INDICES: [u32; 12] = [1, 0, 17, 16, 19, 18, 14, 15, 22, 23, 20, 21];
-pub const GPIO6_BATCHED_PIN_OFFSETS: [[u32; BATCH_SIZE]; 3] = [
-    [P1::OFFSET, P0::OFFSET, P17::OFFSET, P16::OFFSET],
-    [P19::OFFSET, P18::OFFSET, P14::OFFSET, P15::OFFSET],
-    [P22::OFFSET, P23::OFFSET, P20::OFFSET, P21::OFFSET],
+// The bit offsets are spelled out, so the layout is known to host builds as well. Teensy builds
+// check them against the board support crate.
+
+// Every pin that can be driven through GPIO6, in column order. A panel uses as many of them as it
+// has columns, starting from the first one.
+pub const LED_OUTPUT_PIN_INDICES: [u32; 16] =
+    [1, 0, 17, 16, 19, 18, 14, 15, 22, 23, 20, 21, 24, 25, 26, 27];
+pub const GPIO6_BATCHED_PIN_OFFSETS: [[u32; BATCH_SIZE];
+    LED_OUTPUT_PIN_INDICES.len() / BATCH_SIZE] = [
+    [2, 3, 22, 23],
+    [16, 17, 18, 19],
+    [24, 25, 26, 27],
+    [12, 13, 30, 31],
 ];
 
+// GPIO9 bit offsets of pin 3 (SRCLK) and pin 2 (RCLK)
+pub const SHIFT_CLOCK_OFFSET: u32 = 5;
+pub const LATCH_CLOCK_OFFSET: u32 = 4;
+
+#[cfg(target_arch = "arm")]
+const _: () = {
+    let bsp_offsets = [
+        [P1::OFFSET, P0::OFFSET, P17::OFFSET, P16::OFFSET],
+        [P19::OFFSET, P18::OFFSET, P14::OFFSET, P15::OFFSET],
+        [P22::OFFSET, P23::OFFSET, P20::OFFSET, P21::OFFSET],
+        [P24::OFFSET, P25::OFFSET, P26::OFFSET, P27::OFFSET],
+    ];
+
+    let mut batch = 0;
+    while batch < bsp_offsets.len() {
+        let mut lane = 0;
+        while lane < BATCH_SIZE {
+            assert!(
+                GPIO6_BATCHED_PIN_OFFSETS[batch][lane] == bsp_offsets[batch][lane],
+                "GPIO6_BATCHED_PIN_OFFSETS doesn't match the pins"
+            );
+            lane += 1;
+        }
+        batch += 1;
+    }
+
+    assert!(
+        SHIFT_CLOCK_OFFSET == P3::OFFSET && LATCH_CLOCK_OFFSET == P2::OFFSET,
+        "the clock offsets don't match the pins"
+    );
+};
+
+#[cfg(target_arch = "arm")]
 pub fn led_output_pin_setup<P: Iomuxc>(pin: &mut P, bit_offset: u32) {
     // configure to be GPIO, which is done by setting ALT to 5
     alternate(pin, 5);
@@ -41,6 +88,7 @@ pub fn led_output_pin_setup<P: Iomuxc>(pin: &mut P, bit_offset: u32) {
     modify_reg!(ral::gpio, peripherals::gpio6(), GDIR, |gdir| gdir | pin_bit);
 }
 
+#[cfg(target_arch = "arm")]
 pub fn clock_pin_setup<P: Iomuxc>(pin: &mut P, bit_offset: u32) {
     // configure to be GPIO, which is done by setting ALT to 5
     alternate(pin, 5);
@@ -68,6 +116,51 @@ pub fn clock_pin_setup<P: Iomuxc>(pin: &mut P, bit_offset: u32) {
     modify_reg!(ral::gpio, peripherals::gpio9(), GDIR, |gdir| gdir | pin_bit);
 }
 
+#[cfg(target_arch = "arm")]
+pub fn clock_pin_flexio_setup<P: Iomuxc>(pin: &mut P, alt: u32) {
+    // hand the pin over to FlexIO1, which drives it from a timer
+    alternate(pin, alt);
+    clear_sion(pin);
+    configure(
+        pin,
+        Config::zero()
+            .set_speed(Speed::Max)
+            .set_drive_strength(DriveStrength::R0)
+            .set_pull_keeper(None)
+            .set_hysteresis(Hysteresis::Disabled)
+            .set_slew_rate(SlewRate::Fast)
+            .set_open_drain(OpenDrain::Disabled),
+    );
+}
+
+// The DMA can't reach the high-speed GPIO ports, so these hand a pin that was already set up back
+// to its normal GPIO port. GPIO6 maps to GPIO1 and GPIO9 maps to GPIO4, with the same bit offsets.
+
+#[cfg(target_arch = "arm")]
+pub fn led_output_pin_use_normal_gpio(bit_offset: u32) {
+    let pin_bit = 1 << bit_offset;
+    modify_reg!(
+        ral::iomuxc_gpr,
+        peripherals::iomuxc_gpr(),
+        GPR26,
+        |gpr26| gpr26 & !pin_bit
+    );
+    modify_reg!(ral::gpio, peripherals::gpio1(), GDIR, |gdir| gdir | pin_bit);
+}
+
+#[cfg(target_arch = "arm")]
+pub fn clock_pin_use_normal_gpio(bit_offset: u32) {
+    let pin_bit = 1 << bit_offset;
+    modify_reg!(
+        ral::iomuxc_gpr,
+        peripherals::iomuxc_gpr(),
+        GPR29,
+        |gpr29| gpr29 & !pin_bit
+    );
+    modify_reg!(ral::gpio, peripherals::gpio4(), GDIR, |gdir| gdir | pin_bit);
+}
+
+#[cfg(target_arch = "arm")]
 pub fn button_pin_setup<P: Iomuxc>(pin: &mut P, bit_offset: u32) {
     // configure to be GPIO, which is done by setting ALT to 5
     alternate(pin, 5);
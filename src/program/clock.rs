@@ -1,13 +1,22 @@
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
 
-use teensy4_bsp::hal::snvs;
-use teensy4_bsp::hal::snvs::srtc::Srtc;
-use teensy4_bsp::hal::snvs::*;
+use chrono::Timelike;
 
 use super::Program;
-use crate::color::{self, AdjustedColor, Color, BLACK};
+use crate::alarm::{Alarm, AlarmClock, Weekdays};
+use crate::button::ButtonEvent;
+use crate::color::{AdjustedColor, Color};
+use crate::font::FONT_3X5;
+use crate::framebuffer::BackBuffer;
 use crate::led_driver::{FrameRate, ScreenDriver};
-use crate::peripherals;
+use crate::low_power::{LowPowerDomain, PlatformLowPower};
+use crate::settings::Settings;
+use crate::sprite::{BlitOptions, Sprite};
+use crate::time::{
+    self, DstRule, HourFormat, TimeZone, SECONDS_PER_DAY, SECONDS_PER_HOUR, SECONDS_PER_MINUTE,
+};
 
 #[rustfmt::skip]
 const NUMBER_STENCILS: [[[u8; 3]; 5]; 10] = [
@@ -105,85 +114,512 @@ const LOWER_M_STENCIL: [[u8; 4]; 3] = [
 ];
 
 const TEXT_COLOR: AdjustedColor = Color::from_rgb(0xAA, 0xAA, 0xAA).adjust_for_led();
+// lit in the corner while an alarm or sunrise field is being set
+const ALARM_MARK_COLOR: AdjustedColor = Color::from_rgb(255, 128, 0).adjust_for_led();
+// and this one while a time zone field is
+const ZONE_MARK_COLOR: AdjustedColor = Color::from_rgb(0, 128, 255).adjust_for_led();
 
-const NUMBER_GLYPHS: [[[AdjustedColor; 3]; 5]; 10] =
-    process_glyph_array(NUMBER_STENCILS, TEXT_COLOR);
-const LOWER_A_GLYPH: [[AdjustedColor; 3]; 3] = process_glyph(LOWER_A_STENCIL, TEXT_COLOR);
-const LOWER_P_GLYPH: [[AdjustedColor; 3]; 3] = process_glyph(LOWER_P_STENCIL, TEXT_COLOR);
-const LOWER_M_GLYPH: [[AdjustedColor; 4]; 3] = process_glyph(LOWER_M_STENCIL, TEXT_COLOR);
-
-const fn process_glyph<const WIDTH: usize, const HEIGHT: usize>(
-    stencil: [[u8; WIDTH]; HEIGHT],
-    color: AdjustedColor,
-) -> [[AdjustedColor; WIDTH]; HEIGHT] {
-    let mut processed_colors = [[BLACK.adjust_for_led(); WIDTH]; HEIGHT];
-
-    let mut y = 0;
-    while y < HEIGHT {
-        let mut x = 0;
-        while x < WIDTH {
-            if stencil[y][x] != 0 {
-                processed_colors[y][x] = color;
-            }
-            x += 1;
-        }
-        y += 1;
+const NUMBER_GLYPHS: [Sprite<3, 5>; 10] = Sprite::array_from_stencils(NUMBER_STENCILS, TEXT_COLOR);
+const LOWER_A_GLYPH: Sprite<3, 3> = Sprite::from_stencil(LOWER_A_STENCIL, TEXT_COLOR);
+const LOWER_P_GLYPH: Sprite<3, 3> = Sprite::from_stencil(LOWER_P_STENCIL, TEXT_COLOR);
+const LOWER_M_GLYPH: Sprite<4, 3> = Sprite::from_stencil(LOWER_M_STENCIL, TEXT_COLOR);
+
+// every page stays up for this long before switching between hours and minutes
+const PAGE_SECONDS: u32 = 2;
+
+const DIGIT_WIDTH: i32 = 3;
+const DIGIT_HEIGHT: i32 = 5;
+const GLYPH_SPACING: i32 = 1;
+// two digits and a colon
+const TIME_WIDTH: i32 = DIGIT_WIDTH * 2 + GLYPH_SPACING + GLYPH_SPACING + 1;
+// "am" or "pm", which has the same width for both
+const PERIOD_WIDTH: i32 = 3 + GLYPH_SPACING + 4;
+
+// the button only reaches the first alarm, and the other slots keep whatever is stored in them
+const EDITED_ALARM_SLOT: usize = 0;
+
+// the UTC offsets that the button counts through, in hours
+const MIN_ZONE_HOURS: i32 = -12;
+const MAX_ZONE_HOURS: i32 = 14;
+// and the sunrise lengths, in minutes
+const SUNRISE_MINUTES_STEP: u32 = 5;
+const MAX_SUNRISE_MINUTES: u32 = 60;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum EditField {
+    Hours,
+    Minutes,
+    AlarmHours,
+    AlarmMinutes,
+    Sunrise,
+    ZoneHours,
+    ZoneMinutes,
+    Dst,
+    HourFormat,
+}
+
+impl EditField {
+    fn is_hours(self) -> bool {
+        matches!(self, EditField::Hours | EditField::AlarmHours)
     }
 
-    processed_colors
+    fn is_alarm(self) -> bool {
+        matches!(
+            self,
+            EditField::AlarmHours | EditField::AlarmMinutes | EditField::Sunrise
+        )
+    }
+
+    fn mark_color(self) -> Option<AdjustedColor> {
+        match self {
+            EditField::ZoneHours | EditField::ZoneMinutes | EditField::Dst => Some(ZONE_MARK_COLOR),
+            _ if self.is_alarm() => Some(ALARM_MARK_COLOR),
+            _ => None,
+        }
+    }
 }
 
-const fn process_glyph_array<const WIDTH: usize, const HEIGHT: usize, const COUNT: usize>(
-    stencils: [[[u8; WIDTH]; HEIGHT]; COUNT],
-    color: AdjustedColor,
-) -> [[[AdjustedColor; WIDTH]; HEIGHT]; COUNT] {
-    let mut processed_glyphs = [[[BLACK.adjust_for_led(); WIDTH]; HEIGHT]; COUNT];
+// the time gets written to the SRTC once its minutes are done, the alarm once its fields are, the
+// sunrise length after that, then the time zone once its fields are done, and the hour format last
+struct SettingsEdit {
+    field: EditField,
+    // the minutes go in the same direction as the hours
+    zone_hours: i32,
+    zone_minutes: u32,
+    dst_rule: DstRule,
+    hour_format: HourFormat,
+    hours: u32,
+    minutes: u32,
+    // no hours means the alarm is off
+    alarm_hours: Option<u32>,
+    alarm_minutes: u32,
+    sunrise_minutes: u32,
+}
 
-    let mut i = 0;
-    while i < COUNT {
-        processed_glyphs[i] = process_glyph(stencils[i], color);
-        i += 1;
+impl SettingsEdit {
+    fn time_zone(&self) -> TimeZone {
+        let zone_minutes = if self.zone_hours < 0 {
+            -(self.zone_minutes as i32)
+        } else {
+            self.zone_minutes as i32
+        };
+        TimeZone::new(
+            self.zone_hours * SECONDS_PER_HOUR as i32 + zone_minutes * SECONDS_PER_MINUTE as i32,
+            self.dst_rule,
+        )
     }
 
-    processed_glyphs
+    // the settings that don't look like a time are shown as text
+    fn text(&self) -> Option<String> {
+        match self.field {
+            EditField::ZoneHours => Some(format!("{:+}", self.zone_hours)),
+            EditField::ZoneMinutes => Some(format!(":{:02}", self.zone_minutes)),
+            EditField::Dst => Some(String::from(match self.dst_rule {
+                DstRule::None => "--",
+                DstRule::Eu => "EU",
+                DstRule::Us => "US",
+            })),
+            EditField::HourFormat => Some(String::from(match self.hour_format {
+                HourFormat::Twelve => "12h",
+                HourFormat::TwentyFour => "24h",
+            })),
+            EditField::Sunrise if self.sunrise_minutes == 0 => Some(String::from("--")),
+            EditField::Sunrise => Some(format!("{}", self.sunrise_minutes)),
+            _ => None,
+        }
+    }
 }
 
+/// Shows the local time from the SRTC, alternating between the hours and the minutes since both
+/// don't fit next to each other.
+///
+/// A long press of the button starts setting the time, then the first alarm, the sunrise, the time
+/// zone and the hour format. Short presses count the blinking field up, and another long press
+/// moves on to the next field. The alarm hours count through "--" to turn the alarm off, which
+/// skips its minutes. The sunrise is how many minutes it fades in ahead of every alarm, or "--" for
+/// none. Cancelling a sunrise with the button doesn't skip the alarm at the end of it. The time
+/// zone is set as the UTC offset in hours, the quarter hours on top of that, and the daylight
+/// saving rule. The hour format switches between "12h" and "24h".
 pub struct Clock {
-    srtc: Srtc,
+    low_power: PlatformLowPower,
+    edit: Option<SettingsEdit>,
 }
 
 impl Clock {
-    pub fn new(driver: &mut ScreenDriver) -> Box<dyn Program> {
-        let LowPower {
-            mut core,
-            srtc: raw_srtc,
-            ..
-        } = snvs::new(peripherals::snvs()).low_power;
+    // fast enough to blink the field being set, and to pick up short button presses
+    const FRAME_RATE: FrameRate = FrameRate::Fps16;
+
+    pub fn new<const WIDTH: usize, const HEIGHT: usize>(
+        driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+    ) -> Box<dyn Program<WIDTH, HEIGHT>> {
+        let low_power = PlatformLowPower::open();
 
-        let srtc = raw_srtc.enable(&mut core);
+        driver.set_target_frame_rate(Self::FRAME_RATE);
+
+        Box::new(Clock {
+            low_power,
+            edit: None,
+        })
+    }
+
+    // keeps the local day, and starts the minute over
+    fn save_time(&mut self, hours: u32, minutes: u32) {
+        let time_zone = Settings::load().time_zone;
+        let day_start =
+            time_zone.to_local_seconds(self.low_power.time()) / SECONDS_PER_DAY * SECONDS_PER_DAY;
+        let time = day_start + (hours * 60 + minutes) * 60;
+
+        self.low_power.set_time(time_zone.to_utc_seconds(time));
+    }
 
-        driver.set_target_frame_rate(FrameRate::Fps1);
+    // an alarm that was off goes off every day once it is set
+    fn save_alarm(hours: Option<u32>, minutes: u32) {
+        let weekdays = AlarmClock::alarm(EDITED_ALARM_SLOT)
+            .map_or(Weekdays::EVERY_DAY, |alarm| alarm.weekdays);
+        let alarm = hours.map(|hours| Alarm {
+            hours: hours as u8,
+            minutes: minutes as u8,
+            weekdays,
+        });
 
-        Box::new(Clock { srtc })
+        AlarmClock::set_alarm(EDITED_ALARM_SLOT, alarm);
     }
 }
 
-impl Program for Clock {
-    fn render(&mut self, driver: &mut ScreenDriver) {
-        let total_seconds = self.srtc.get();
+impl<const WIDTH: usize, const HEIGHT: usize> Program<WIDTH, HEIGHT> for Clock {
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>) {
+        let (_, micros) = self.low_power.time_with_micros();
 
-        let total_minutes = total_seconds / 60;
-        let minutes = total_minutes % 60;
+        if let Some(edit) = &self.edit {
+            if let Some(text) = edit.text() {
+                let back_buffer = &mut driver.framebuffer.back_buffer;
+                back_buffer.clear();
+
+                if let Some(mark_color) = edit.field.mark_color() {
+                    back_buffer.draw_pixel(0, 0, mark_color);
+                }
+
+                if micros < 500_000 {
+                    let x = (back_buffer.width() as i32 - FONT_3X5.text_width(&text)) / 2;
+                    let y = (back_buffer.height() as i32 - FONT_3X5.height()) / 2;
+                    back_buffer.draw_text(&text, x, y, &FONT_3X5, TEXT_COLOR);
+                }
+                return;
+            }
+        }
+
+        let (hours_24, minutes, showing_hours, blinking) = match &self.edit {
+            Some(edit) if edit.field.is_alarm() => (
+                edit.alarm_hours,
+                edit.alarm_minutes,
+                edit.field.is_hours(),
+                micros >= 500_000,
+            ),
+            Some(edit) => (
+                Some(edit.hours),
+                edit.minutes,
+                edit.field.is_hours(),
+                // the field being set is hidden for half of every second
+                micros >= 500_000,
+            ),
+            None => {
+                let now = time::local_now();
+
+                (
+                    Some(now.hour()),
+                    now.minute(),
+                    (now.second() / PAGE_SECONDS) % 2 == 0,
+                    false,
+                )
+            }
+        };
+
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        back_buffer.clear();
+
+        if let Some(mark_color) = self.edit.as_ref().and_then(|edit| edit.field.mark_color()) {
+            back_buffer.draw_pixel(0, 0, mark_color);
+        }
+
+        let x = (back_buffer.width() as i32 - TIME_WIDTH) / 2;
+        let centered_y = (back_buffer.height() as i32 - DIGIT_HEIGHT) / 2;
+
+        if !showing_hours {
+            // the colon goes in front of the minutes, and after the hours
+            draw_colon(back_buffer, x, centered_y);
+            if !blinking {
+                draw_digits(
+                    back_buffer,
+                    minutes,
+                    true,
+                    x + 1 + GLYPH_SPACING,
+                    centered_y,
+                );
+            }
+            return;
+        }
+
+        let Some(hours_24) = hours_24 else {
+            // the alarm is off
+            if !blinking {
+                draw_dashes(back_buffer, x, centered_y);
+            }
+            draw_colon(back_buffer, x + TIME_WIDTH - 1, centered_y);
+            return;
+        };
 
-        let total_hours = total_minutes / 60;
-        let hours_24 = total_hours % 24;
         let mut hours_12 = hours_24 % 12;
         if hours_12 == 0 {
             hours_12 = 12;
         }
 
-        let pm = hours_24 < 12;
+        let pm = hours_24 >= 12;
+
+        match Settings::load().hour_format {
+            HourFormat::Twelve => {
+                // the hours move up to make room for the period below
+                let y = (back_buffer.height() as i32 - DIGIT_HEIGHT - 3) / 2;
+                if !blinking {
+                    draw_digits(back_buffer, hours_12, false, x, y);
+                }
+                draw_colon(back_buffer, x + TIME_WIDTH - 1, y);
+
+                let period_x = (back_buffer.width() as i32 - PERIOD_WIDTH) / 2;
+                let period_y = y + DIGIT_HEIGHT;
+                let first_glyph = if pm { &LOWER_P_GLYPH } else { &LOWER_A_GLYPH };
+                back_buffer.blit(first_glyph, period_x, period_y, &BlitOptions::DEFAULT);
+                back_buffer.blit(
+                    &LOWER_M_GLYPH,
+                    period_x + 3 + GLYPH_SPACING,
+                    period_y,
+                    &BlitOptions::DEFAULT,
+                );
+            }
+            HourFormat::TwentyFour => {
+                if !blinking {
+                    draw_digits(back_buffer, hours_24, true, x, centered_y);
+                }
+                draw_colon(back_buffer, x + TIME_WIDTH - 1, centered_y);
+            }
+        }
+    }
+
+    fn handle_button(
+        &mut self,
+        event: ButtonEvent,
+        _driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+    ) -> bool {
+        let Some(edit) = &mut self.edit else {
+            if event != ButtonEvent::LongPress {
+                return false;
+            }
+
+            let now = time::local_now();
+            let settings = Settings::load();
+            let time_zone = settings.time_zone;
+            let alarm = AlarmClock::alarm(EDITED_ALARM_SLOT);
+            self.edit = Some(SettingsEdit {
+                field: EditField::Hours,
+                zone_hours: time_zone.utc_offset_seconds / SECONDS_PER_HOUR as i32,
+                zone_minutes: (time_zone.utc_offset_seconds % SECONDS_PER_HOUR as i32)
+                    .unsigned_abs()
+                    / SECONDS_PER_MINUTE,
+                dst_rule: time_zone.dst_rule,
+                hour_format: settings.hour_format,
+                hours: now.hour(),
+                minutes: now.minute(),
+                alarm_hours: alarm.map(|alarm| alarm.hours as u32),
+                alarm_minutes: alarm.map_or(0, |alarm| alarm.minutes as u32),
+                sunrise_minutes: settings.sunrise_minutes,
+            });
+            return true;
+        };
+
+        match (event, edit.field) {
+            (ButtonEvent::ShortPress, EditField::ZoneHours) => {
+                edit.zone_hours = if edit.zone_hours >= MAX_ZONE_HOURS {
+                    MIN_ZONE_HOURS
+                } else {
+                    edit.zone_hours + 1
+                };
+            }
+            (ButtonEvent::ShortPress, EditField::ZoneMinutes) => {
+                edit.zone_minutes = (edit.zone_minutes + 15) % 60;
+            }
+            (ButtonEvent::ShortPress, EditField::Dst) => {
+                edit.dst_rule = match edit.dst_rule {
+                    DstRule::None => DstRule::Eu,
+                    DstRule::Eu => DstRule::Us,
+                    DstRule::Us => DstRule::None,
+                };
+            }
+            (ButtonEvent::ShortPress, EditField::HourFormat) => {
+                edit.hour_format = match edit.hour_format {
+                    HourFormat::Twelve => HourFormat::TwentyFour,
+                    HourFormat::TwentyFour => HourFormat::Twelve,
+                };
+            }
+            (ButtonEvent::ShortPress, EditField::Hours) => edit.hours = (edit.hours + 1) % 24,
+            (ButtonEvent::ShortPress, EditField::Minutes) => edit.minutes = (edit.minutes + 1) % 60,
+            (ButtonEvent::ShortPress, EditField::AlarmHours) => {
+                // counts up to 23, then through off, and back to 0
+                edit.alarm_hours = match edit.alarm_hours {
+                    Some(23) => None,
+                    Some(hours) => Some(hours + 1),
+                    None => Some(0),
+                };
+            }
+            (ButtonEvent::ShortPress, EditField::AlarmMinutes) => {
+                edit.alarm_minutes = (edit.alarm_minutes + 1) % 60;
+            }
+            (ButtonEvent::ShortPress, EditField::Sunrise) => {
+                edit.sunrise_minutes = (edit.sunrise_minutes + SUNRISE_MINUTES_STEP)
+                    % (MAX_SUNRISE_MINUTES + SUNRISE_MINUTES_STEP);
+            }
+            (ButtonEvent::LongPress, EditField::Hours) => edit.field = EditField::Minutes,
+            (ButtonEvent::LongPress, EditField::Minutes) => {
+                let (hours, minutes) = (edit.hours, edit.minutes);
+                edit.field = EditField::AlarmHours;
+                self.save_time(hours, minutes);
+            }
+            (ButtonEvent::LongPress, EditField::AlarmHours) if edit.alarm_hours.is_some() => {
+                edit.field = EditField::AlarmMinutes;
+            }
+            (ButtonEvent::LongPress, EditField::AlarmHours | EditField::AlarmMinutes) => {
+                Self::save_alarm(edit.alarm_hours, edit.alarm_minutes);
+                edit.field = EditField::Sunrise;
+            }
+            (ButtonEvent::LongPress, EditField::Sunrise) => {
+                Settings {
+                    sunrise_minutes: edit.sunrise_minutes,
+                    ..Settings::load()
+                }
+                .save();
+                edit.field = EditField::ZoneHours;
+            }
+            (ButtonEvent::LongPress, EditField::ZoneHours) => edit.field = EditField::ZoneMinutes,
+            (ButtonEvent::LongPress, EditField::ZoneMinutes) => edit.field = EditField::Dst,
+            (ButtonEvent::LongPress, EditField::Dst) => {
+                // the SRTC stays in UTC, so the time that was set moves along with the new zone
+                Settings {
+                    time_zone: edit.time_zone(),
+                    ..Settings::load()
+                }
+                .save();
+                edit.field = EditField::HourFormat;
+            }
+            (ButtonEvent::LongPress, EditField::HourFormat) => {
+                Settings {
+                    hour_format: edit.hour_format,
+                    ..Settings::load()
+                }
+                .save();
+                self.edit = None;
+            }
+        }
+
+        true
+    }
+}
+
+// draws a number below 100 as two digits, where the leading zero can be left as a blank
+fn draw_digits<const WIDTH: usize, const HEIGHT: usize>(
+    back_buffer: &mut BackBuffer<WIDTH, HEIGHT>,
+    value: u32,
+    leading_zero: bool,
+    x: i32,
+    y: i32,
+) {
+    let tens = (value / 10) % 10;
+    let ones = value % 10;
+
+    if tens != 0 || leading_zero {
+        back_buffer.blit(&NUMBER_GLYPHS[tens as usize], x, y, &BlitOptions::DEFAULT);
+    }
+    back_buffer.blit(
+        &NUMBER_GLYPHS[ones as usize],
+        x + DIGIT_WIDTH + GLYPH_SPACING,
+        y,
+        &BlitOptions::DEFAULT,
+    );
+}
+
+// two digits' worth of dashes, where an alarm that is off would show its hours
+fn draw_dashes<const WIDTH: usize, const HEIGHT: usize>(
+    back_buffer: &mut BackBuffer<WIDTH, HEIGHT>,
+    x: i32,
+    y: i32,
+) {
+    back_buffer.fill_rect(x, y + 2, DIGIT_WIDTH, 1, TEXT_COLOR);
+    back_buffer.fill_rect(
+        x + DIGIT_WIDTH + GLYPH_SPACING,
+        y + 2,
+        DIGIT_WIDTH,
+        1,
+        TEXT_COLOR,
+    );
+}
+
+// two dots lined up with the digits next to it
+fn draw_colon<const WIDTH: usize, const HEIGHT: usize>(
+    back_buffer: &mut BackBuffer<WIDTH, HEIGHT>,
+    x: i32,
+    y: i32,
+) {
+    back_buffer.draw_pixel(x, y + 1, TEXT_COLOR);
+    back_buffer.draw_pixel(x, y + 3, TEXT_COLOR);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::FrameHandoff;
+
+    // Monday the 1st of January 2024, at midnight UTC
+    const MONDAY: u32 = 1_704_067_200;
+
+    fn press<const WIDTH: usize, const HEIGHT: usize>(
+        clock: &mut dyn Program<WIDTH, HEIGHT>,
+        driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+        events: &[ButtonEvent],
+    ) {
+        for &event in events {
+            assert!(clock.handle_button(event, driver));
+        }
+    }
+
+    #[test]
+    fn every_field_gets_saved() {
+        let mut low_power = PlatformLowPower::open();
+        low_power.set_time(MONDAY + 10 * SECONDS_PER_HOUR + 20 * SECONDS_PER_MINUTE + 30);
+
+        let handoff = Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps64.rtc_mask())));
+        let mut driver: ScreenDriver = ScreenDriver::with_handoff(handoff);
+        let mut clock = Clock::new(&mut driver);
+
+        use ButtonEvent::{LongPress, ShortPress};
+        // into the edit mode, and two hours later
+        let time = [LongPress, ShortPress, ShortPress, LongPress, LongPress];
+        press(clock.as_mut(), &mut driver, &time);
+        // leaves the alarm off and the sunrise as it was
+        press(clock.as_mut(), &mut driver, &[LongPress, LongPress]);
+        // one hour east with EU daylight saving time, and 24 hour time
+        let zone = [ShortPress, LongPress, LongPress, ShortPress, LongPress];
+        press(clock.as_mut(), &mut driver, &zone);
+        press(clock.as_mut(), &mut driver, &[ShortPress, LongPress]);
+        assert!(!clock.handle_button(ShortPress, &mut driver));
+
+        // the minute starts over, and the time was set while the zone was still UTC
+        assert_eq!(
+            low_power.time(),
+            MONDAY + 12 * SECONDS_PER_HOUR + 20 * SECONDS_PER_MINUTE
+        );
+        assert_eq!(time::local_now().hour(), 13);
 
-        driver.drive_mid_render();
+        let settings = Settings::load();
+        assert!(settings.time_zone == TimeZone::CENTRAL_EUROPE);
+        assert!(settings.hour_format == HourFormat::TwentyFour);
+        assert!(settings.sunrise_minutes == AlarmClock::DEFAULT_SUNRISE_MINUTES);
+        assert!(AlarmClock::alarm(EDITED_ALARM_SLOT).is_none());
     }
 }
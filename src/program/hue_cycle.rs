@@ -1,31 +1,24 @@
 use alloc::boxed::Box;
 
-use crate::color::Color;
-use crate::framebuffer::Framebuffer;
+use crate::color::{Color, BLACK};
 use crate::led_driver::{FrameRate, ScreenDriver};
 use crate::program::Program;
 
-pub struct HueCycle {
-    scratch_buffer: [[Color; Framebuffer::WIDTH]; Framebuffer::HEIGHT],
+pub struct HueCycle<const WIDTH: usize, const HEIGHT: usize> {
+    scratch_buffer: [[Color; WIDTH]; HEIGHT],
 }
 
-impl HueCycle {
-    pub fn new(driver: &mut ScreenDriver) -> Box<dyn Program> {
+impl<const WIDTH: usize, const HEIGHT: usize> HueCycle<WIDTH, HEIGHT> {
+    pub fn new(driver: &mut ScreenDriver<WIDTH, HEIGHT>) -> Box<dyn Program<WIDTH, HEIGHT>> {
         let mut program = Box::new(Self {
-            scratch_buffer: Default::default(),
+            scratch_buffer: [[BLACK; WIDTH]; HEIGHT],
         });
 
-        for y in 0..Framebuffer::HEIGHT {
-            for x in 0..Framebuffer::WIDTH {
-                *unsafe {
-                    program
-                        .scratch_buffer
-                        .get_mut(y)
-                        .unwrap_unchecked()
-                        .get_mut(x)
-                        .unwrap_unchecked()
-                } = Color::from_rgb(((x + y) * 10) as u8, 255, 0);
-            }
+        // the scratch buffer is laid out in the logical size, which may be turned
+        let width = driver.width();
+        for (i, color) in program.scratch_buffer.flatten_mut().iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            *color = Color::from_rgb(((x + y) * 10) as u8, 255, 0);
         }
         driver.set_target_frame_rate(FrameRate::Fps512);
         // necessary to make sure the front buffer is initialized
@@ -35,43 +28,35 @@ impl HueCycle {
     }
 }
 
-impl Program for HueCycle {
-    fn render(&mut self, driver: &mut ScreenDriver) {
-        for y in 0..Framebuffer::HEIGHT {
-            for x in 0..Framebuffer::WIDTH {
-                let color = unsafe {
-                    self.scratch_buffer
-                        .get_mut(y)
-                        .unwrap_unchecked()
-                        .get_mut(x)
-                        .unwrap_unchecked()
-                };
-
-                color.g = color
-                    .g
-                    .saturating_add((color.r == 0xFF && color.b == 0x00) as u8);
-                color.g = color
-                    .g
-                    .saturating_sub((color.b == 0xFF && color.r == 0x00) as u8);
-
-                color.b = color
-                    .b
-                    .saturating_add((color.g == 0xFF && color.r == 0x00) as u8);
-                color.b = color
-                    .b
-                    .saturating_sub((color.r == 0xFF && color.g == 0x00) as u8);
-
-                color.r = color
-                    .r
-                    .saturating_add((color.b == 0xFF && color.g == 0x00) as u8);
-                color.r = color
-                    .r
-                    .saturating_sub((color.g == 0xFF && color.b == 0x00) as u8);
-
-                driver.framebuffer.back_buffer.set_led(x, y, *color);
-
-                driver.drive_mid_render();
-            }
+impl<const WIDTH: usize, const HEIGHT: usize> Program<WIDTH, HEIGHT> for HueCycle<WIDTH, HEIGHT> {
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>) {
+        let width = driver.width();
+
+        for (i, color) in self.scratch_buffer.flatten_mut().iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+
+            color.g = color
+                .g
+                .saturating_add((color.r == 0xFF && color.b == 0x00) as u8);
+            color.g = color
+                .g
+                .saturating_sub((color.b == 0xFF && color.r == 0x00) as u8);
+
+            color.b = color
+                .b
+                .saturating_add((color.g == 0xFF && color.r == 0x00) as u8);
+            color.b = color
+                .b
+                .saturating_sub((color.r == 0xFF && color.g == 0x00) as u8);
+
+            color.r = color
+                .r
+                .saturating_add((color.b == 0xFF && color.g == 0x00) as u8);
+            color.r = color
+                .r
+                .saturating_sub((color.g == 0xFF && color.b == 0x00) as u8);
+
+            driver.framebuffer.back_buffer.set_led(x, y, *color);
         }
     }
 }
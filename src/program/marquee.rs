@@ -0,0 +1,316 @@
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use critical_section::Mutex;
+
+use crate::button::ButtonEvent;
+use crate::color::Color;
+use crate::font::{Font, FONT_5X7};
+use crate::led_driver::{FrameRate, ScreenDriver};
+use crate::program::Program;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum ScrollDirection {
+    /// The text comes in from the right edge, like a news ticker.
+    Left,
+    /// The text comes in from the left edge.
+    Right,
+}
+
+#[derive(Clone)]
+pub struct MarqueeSettings {
+    pub message: Cow<'static, str>,
+    pub font: &'static Font,
+    pub color: Color,
+    pub pixels_per_second: u32,
+    pub direction: ScrollDirection,
+}
+
+impl MarqueeSettings {
+    pub const DEFAULT: Self = Self {
+        message: Cow::Borrowed("Hello!"),
+        font: &FONT_5X7,
+        color: Color::from_rgb(0xAA, 0xAA, 0xAA),
+        pixels_per_second: 12,
+        direction: ScrollDirection::Left,
+    };
+}
+
+/// The settings shared with the marquee program. Programs are only reachable as trait objects
+/// once they are running, so this is how the message gets changed from the outside. A running
+/// marquee picks changes up on its next frame.
+pub struct MarqueeControl {
+    settings: Mutex<RefCell<MarqueeSettings>>,
+    revision: AtomicU32,
+    message_set: AtomicBool,
+}
+
+impl MarqueeControl {
+    pub const fn new(settings: MarqueeSettings) -> Self {
+        Self {
+            settings: Mutex::new(RefCell::new(settings)),
+            revision: AtomicU32::new(0),
+            message_set: AtomicBool::new(false),
+        }
+    }
+
+    pub fn update(&self, update_settings: impl FnOnce(&mut MarqueeSettings)) {
+        critical_section::with(|cs| update_settings(&mut self.settings.borrow_ref_mut(cs)));
+        self.revision.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn set_message(&self, message: impl Into<Cow<'static, str>>) {
+        self.message_set.store(true, Ordering::Relaxed);
+        let message = message.into();
+        self.update(|settings| settings.message = message);
+    }
+
+    /// Changes the message, unless one has been set with [`MarqueeControl::set_message`].
+    pub fn set_default_message(&self, message: impl Into<Cow<'static, str>>) {
+        if self.message_set.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let message = message.into();
+        self.update(|settings| settings.message = message);
+    }
+
+    fn revision(&self) -> u32 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    fn settings(&self) -> MarqueeSettings {
+        critical_section::with(|cs| self.settings.borrow_ref(cs).clone())
+    }
+}
+
+pub static MARQUEE_CONTROL: MarqueeControl = MarqueeControl::new(MarqueeSettings::DEFAULT);
+
+// a long press moves on to the next of these
+const SCROLL_PRESETS: [(ScrollDirection, u32); 4] = [
+    (ScrollDirection::Left, 12),
+    (ScrollDirection::Left, 24),
+    (ScrollDirection::Right, 12),
+    (ScrollDirection::Right, 24),
+];
+
+/// Scrolls the message from a [`MarqueeControl`] across the panel, which is [`MARQUEE_CONTROL`]
+/// unless the marquee is made with [`Marquee::with_control`]. The message comes in from the
+/// edge, and repeats behind itself with a gap in between, so the loop never jumps.
+///
+/// A long press of the button switches between a few speeds and directions.
+pub struct Marquee<const WIDTH: usize, const HEIGHT: usize> {
+    control: &'static MarqueeControl,
+    settings: MarqueeSettings,
+    revision: u32,
+    text_width: i32,
+    // the x of every repetition that is on the panel or next to come in, from left to right
+    repetitions: VecDeque<i32>,
+    // in pixels times frames per second, carried over until it adds up to a whole pixel
+    scroll_remainder: u32,
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Marquee<WIDTH, HEIGHT> {
+    // the empty space between the end of the message and its next repetition
+    pub const MESSAGE_GAP: i32 = 4;
+
+    // the speed only changes how many pixels every frame scrolls by
+    const FRAME_RATE: FrameRate = FrameRate::Fps64;
+    const FRAMES_PER_SECOND: u32 = 64;
+
+    pub fn new(driver: &mut ScreenDriver<WIDTH, HEIGHT>) -> Box<dyn Program<WIDTH, HEIGHT>> {
+        Self::with_control(driver, &MARQUEE_CONTROL)
+    }
+
+    pub fn with_control(
+        driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+        control: &'static MarqueeControl,
+    ) -> Box<dyn Program<WIDTH, HEIGHT>> {
+        let mut program = Box::new(Self {
+            control,
+            settings: control.settings(),
+            revision: control.revision(),
+            text_width: 0,
+            repetitions: VecDeque::new(),
+            scroll_remainder: 0,
+        });
+        program.restart_message(driver.width() as i32);
+        driver.set_target_frame_rate(Self::FRAME_RATE);
+
+        program
+    }
+
+    fn loop_length(&self) -> i32 {
+        self.text_width + Self::MESSAGE_GAP
+    }
+
+    // places a single repetition just outside of the edge that the message comes in from
+    fn restart_message(&mut self, panel_width: i32) {
+        self.text_width = self.settings.font.text_width(&self.settings.message);
+
+        let entry_x = match self.settings.direction {
+            ScrollDirection::Left => panel_width,
+            ScrollDirection::Right => -self.text_width,
+        };
+        self.repetitions.clear();
+        self.repetitions.push_back(entry_x);
+    }
+
+    fn sync_settings(&mut self, panel_width: i32) {
+        let revision = self.control.revision();
+        if revision == self.revision {
+            return;
+        }
+
+        let settings = self.control.settings();
+        let message_changed = settings.message != self.settings.message
+            || !core::ptr::eq(settings.font, self.settings.font);
+
+        self.settings = settings;
+        self.revision = revision;
+
+        // the repetitions stay where they are when only the direction, speed or color changes
+        if message_changed {
+            self.restart_message(panel_width);
+        }
+    }
+
+    // moves every repetition by a pixel, drops the ones that went off the panel, and lines up the
+    // next one behind the last one that started coming in. Repetitions that are further in than
+    // the gap, like after a change of direction, get the next one just outside of the edge instead
+    fn scroll(&mut self, panel_width: i32) {
+        let text_width = self.text_width;
+        let loop_length = self.loop_length();
+        let repetitions = &mut self.repetitions;
+
+        match self.settings.direction {
+            ScrollDirection::Left => {
+                repetitions.iter_mut().for_each(|x| *x -= 1);
+                repetitions.retain(|&x| x + text_width > 0);
+
+                match repetitions.back() {
+                    Some(&last) if last < panel_width => {
+                        repetitions.push_back((last + loop_length).max(panel_width));
+                    }
+                    Some(_) => {}
+                    None => repetitions.push_back(panel_width),
+                }
+            }
+            ScrollDirection::Right => {
+                repetitions.iter_mut().for_each(|x| *x += 1);
+                repetitions.retain(|&x| x < panel_width);
+
+                match repetitions.front() {
+                    Some(&first) if first + text_width > 0 => {
+                        repetitions.push_front((first - loop_length).min(-text_width));
+                    }
+                    Some(_) => {}
+                    None => repetitions.push_front(-text_width),
+                }
+            }
+        }
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Program<WIDTH, HEIGHT> for Marquee<WIDTH, HEIGHT> {
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>) {
+        let panel_width = driver.width() as i32;
+        self.sync_settings(panel_width);
+
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        back_buffer.clear();
+
+        let font = self.settings.font;
+        let y = (back_buffer.height() as i32 - font.height()) / 2;
+        let color = self.settings.color.adjust_for_led();
+
+        for &x in self.repetitions.iter() {
+            back_buffer.draw_text(&self.settings.message, x, y, font, color);
+        }
+
+        self.scroll_remainder += self.settings.pixels_per_second;
+        while self.scroll_remainder >= Self::FRAMES_PER_SECOND {
+            self.scroll_remainder -= Self::FRAMES_PER_SECOND;
+            self.scroll(panel_width);
+        }
+    }
+
+    fn handle_button(
+        &mut self,
+        event: ButtonEvent,
+        _driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+    ) -> bool {
+        if event != ButtonEvent::LongPress {
+            return false;
+        }
+
+        let current = SCROLL_PRESETS
+            .iter()
+            .position(|&(direction, pixels_per_second)| {
+                direction == self.settings.direction
+                    && pixels_per_second == self.settings.pixels_per_second
+            });
+        let (direction, pixels_per_second) =
+            SCROLL_PRESETS[current.map_or(0, |index| (index + 1) % SCROLL_PRESETS.len())];
+        self.control.update(|settings| {
+            settings.direction = direction;
+            settings.pixels_per_second = pixels_per_second;
+        });
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::AdjustedColor;
+    use crate::framebuffer::{FrameHandoff, PANEL_HEIGHT, PANEL_WIDTH};
+
+    type PanelMarquee = Marquee<PANEL_WIDTH, PANEL_HEIGHT>;
+
+    #[test]
+    fn message_comes_in_from_the_edge() {
+        let handoff = Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps64.rtc_mask())));
+        let mut driver: ScreenDriver = ScreenDriver::with_handoff(handoff);
+        // the shared control could be changed by other tests at the same time
+        static CONTROL: MarqueeControl = MarqueeControl::new(MarqueeSettings::DEFAULT);
+        let mut marquee = Marquee::with_control(&mut driver, &CONTROL);
+
+        let width = driver.width();
+        let speed = MarqueeSettings::DEFAULT.pixels_per_second as usize;
+        let frames_per_pixel = PanelMarquee::FRAMES_PER_SECOND as usize / speed;
+
+        // nothing shows up ahead of the message, which starts just outside of the right edge
+        let mut leftmost_lit = width;
+        for frame in 0..frames_per_pixel * (width + 1) {
+            marquee.render(&mut driver);
+            let back_buffer = &driver.framebuffer.back_buffer;
+
+            let lit = (0..width).find(|&x| {
+                (0..driver.height())
+                    .any(|y| back_buffer.get_led_adjusted(x, y) != AdjustedColor::default())
+            });
+            let scrolled = frame * speed / PanelMarquee::FRAMES_PER_SECOND as usize;
+            assert!(lit.map_or(true, |x| x + scrolled >= width && x <= leftmost_lit));
+            leftmost_lit = lit.unwrap_or(leftmost_lit);
+        }
+
+        assert_eq!(leftmost_lit, 0);
+    }
+
+    #[test]
+    fn default_message_leaves_a_set_message_alone() {
+        let control = MarqueeControl::new(MarqueeSettings::DEFAULT);
+
+        control.set_default_message("Mon 1 Jan");
+        assert_eq!(control.settings().message, "Mon 1 Jan");
+
+        control.set_message("Wake up");
+        control.set_default_message("Tue 2 Jan");
+        assert_eq!(control.settings().message, "Wake up");
+    }
+}
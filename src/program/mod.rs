@@ -1,18 +1,42 @@
 mod clock;
 mod hue_cycle;
+mod marquee;
+mod palette_cycle;
 mod rain;
+mod white_balance;
 
 use alloc::boxed::Box;
 
 pub use clock::Clock;
 pub use hue_cycle::HueCycle;
+pub use marquee::{Marquee, MARQUEE_CONTROL};
+pub use palette_cycle::PaletteCycle;
 pub use rain::Rain;
+pub use white_balance::WhiteBalance;
 
+use crate::button::ButtonEvent;
+use crate::framebuffer::{PANEL_HEIGHT, PANEL_WIDTH};
 use crate::led_driver::ScreenDriver;
 
-pub const PROGRAM_CONSTRUCTORS: [fn(&mut ScreenDriver) -> Box<dyn Program>; 3] =
-    [HueCycle::new, Rain::new, Clock::new];
+pub const PROGRAM_CONSTRUCTORS: &[fn(&mut ScreenDriver) -> Box<dyn Program>] = &[
+    HueCycle::new,
+    PaletteCycle::new,
+    Rain::new,
+    Clock::new,
+    Marquee::new,
+    WhiteBalance::new,
+];
 
-pub trait Program {
-    fn render(&mut self, driver: &mut ScreenDriver);
+pub trait Program<const WIDTH: usize = PANEL_WIDTH, const HEIGHT: usize = PANEL_HEIGHT> {
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>);
+
+    /// Called with every button event before it can switch to the next program. Returns whether
+    /// the program used the event, in which case the program stays.
+    fn handle_button(
+        &mut self,
+        _event: ButtonEvent,
+        _driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+    ) -> bool {
+        false
+    }
 }
@@ -0,0 +1,65 @@
+use alloc::boxed::Box;
+
+use crate::color::Color;
+use crate::led_driver::{FrameRate, ScreenDriver};
+use crate::palette::{IndexedCanvas, Palette};
+use crate::program::Program;
+
+const PALETTE_SIZE: usize = 64;
+// the highest that the waves add up to, which is half of each of their periods
+const MAX_LEVEL: usize = 12 + 10 + 20;
+
+/// Overlapping waves of palette indices, which are drawn once and never change. Only the palette
+/// moves, by an entry every frame, which makes the colors flow along the waves.
+pub struct PaletteCycle<const WIDTH: usize, const HEIGHT: usize> {
+    canvas: IndexedCanvas<PALETTE_SIZE, WIDTH, HEIGHT>,
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> PaletteCycle<WIDTH, HEIGHT> {
+    // ends where it starts, so there is no seam where the entries wrap around
+    const PALETTE: Palette<PALETTE_SIZE> = Palette::gradient(&[
+        Color::from_rgb(255, 0, 64),
+        Color::from_rgb(255, 160, 0),
+        Color::from_rgb(0, 200, 160),
+        Color::from_rgb(64, 0, 255),
+        Color::from_rgb(255, 0, 64),
+    ]);
+
+    pub fn new(driver: &mut ScreenDriver<WIDTH, HEIGHT>) -> Box<dyn Program<WIDTH, HEIGHT>> {
+        let mut canvas = IndexedCanvas::new(Self::PALETTE);
+
+        // the waves are laid out in the logical size, which may be turned
+        canvas.fit_to(&driver.framebuffer.back_buffer);
+        let (width, height) = (canvas.width(), canvas.height());
+        for y in 0..height {
+            for x in 0..width {
+                let (from_center_x, from_center_y) =
+                    (x.abs_diff(width / 2), y.abs_diff(height / 2));
+                let level = triangle(x * 2, 24)
+                    + triangle(y * 3 + x, 20)
+                    + triangle(from_center_x.pow(2) + from_center_y.pow(2), 40);
+                canvas.set_index(x, y, (level * PALETTE_SIZE / (MAX_LEVEL + 1)) as u8);
+            }
+        }
+
+        driver.set_target_frame_rate(FrameRate::Fps32);
+
+        Box::new(Self { canvas })
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Program<WIDTH, HEIGHT>
+    for PaletteCycle<WIDTH, HEIGHT>
+{
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>) {
+        self.canvas.palette.cycle(0..PALETTE_SIZE, 1);
+        self.canvas.expand_into(&mut driver.framebuffer.back_buffer);
+    }
+}
+
+// goes from 0 up to half the period and back down, without needing any trigonometry
+fn triangle(value: usize, period: usize) -> usize {
+    let phase = value % period;
+
+    phase.min(period - phase)
+}
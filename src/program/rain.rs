@@ -1,23 +1,38 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use rand::rngs::SmallRng;
 use rand::{RngCore, SeedableRng};
+#[cfg(target_arch = "arm")]
 use teensy4_bsp::hal::trng::{RetryCount, SampleMode, Trng};
 
-use crate::collections::InlineVec;
-use crate::color::{AdjustedColor, Color};
-use crate::framebuffer::{BackBuffer, ColorLines, Framebuffer};
+use crate::antialias::{subpixels, SUBPIXEL_ONE};
+use crate::color::{AdjustedColor, BlendMode, Color};
+use crate::layer::LayerStack;
 use crate::led_driver::{FrameRate, ScreenDriver};
+#[cfg(target_arch = "arm")]
 use crate::peripherals;
 use crate::program::Program;
 
-pub struct Rain {
+/// Rain falling along the X axis onto the ground at the far edge. The drops are laid out for the
+/// width and height that programs draw in, so they follow the orientation. Falling drops move a
+/// fraction of a pixel every frame, and get spread over the pixels they're between.
+///
+/// The ground and the drops are drawn on layers of their own, where the drops only ever brighten
+/// the ground below them.
+pub struct Rain<const WIDTH: usize, const HEIGHT: usize> {
     rng: SmallRng,
-    raindrop_lines: [RaindropLine; Framebuffer::WIDTH],
+    layers: LayerStack<2, WIDTH, HEIGHT>,
+    // one line for every column the drops fall through, each with at most a drop per row
+    raindrop_lines: Vec<RaindropLine>,
+    width: usize,
+    height: usize,
     line_shift: usize,
+    // the frame within the current shift of the lines
+    substep: u32,
 }
 
-pub type RaindropLine = InlineVec<{ Framebuffer::HEIGHT }, Raindrop>;
+pub type RaindropLine = Vec<Raindrop>;
 
 pub enum RaindropState {
     Falling,
@@ -38,100 +53,78 @@ impl Raindrop {
     }
 }
 
-impl Rain {
+impl<const WIDTH: usize, const HEIGHT: usize> Rain<WIDTH, HEIGHT> {
     pub const RAINDROP_FREQUENCY: u32 = u32::MAX / 10;
-    pub const GROUND_LEVEL: usize = Framebuffer::WIDTH - 3; // inclusive
-    pub const SPLASH_FREQUENCY: u32 = u32::MAX / (Framebuffer::WIDTH - Self::GROUND_LEVEL) as u32;
+    // the frames it takes a drop to fall by a pixel
+    pub const SUBSTEPS: u32 = 2;
+    // the columns at the far edge that the ground takes up
+    pub const GROUND_DEPTH: usize = 3;
+    pub const SPLASH_FREQUENCY: u32 = u32::MAX / Self::GROUND_DEPTH as u32;
 
     pub const RAINDROP_COLOR: AdjustedColor = Color::from_rgb(200, 200, 200).adjust_for_led();
     pub const GROUND_COLOR: AdjustedColor = Color::from_rgb(36, 40, 43).adjust_for_led();
 
-    pub const BASE_FRAME: BackBuffer = {
-        let mut buffer = BackBuffer {
-            bit_lines: [[0; Framebuffer::WIDTH]; Framebuffer::HEIGHT * ColorLines::COUNT],
-        };
-
-        // fill the ground area
-        let mut y = 0_usize;
-        while y < Framebuffer::HEIGHT {
-            let mut x = Self::GROUND_LEVEL;
-            while x < Framebuffer::WIDTH {
-                buffer.bit_lines[y * ColorLines::COUNT + ColorLines::Red as usize][x] =
-                    Self::GROUND_COLOR.r;
-                buffer.bit_lines[y * ColorLines::COUNT + ColorLines::Green as usize][x] =
-                    Self::GROUND_COLOR.g;
-                buffer.bit_lines[y * ColorLines::COUNT + ColorLines::Blue as usize][x] =
-                    Self::GROUND_COLOR.b;
-
-                x += 1;
-            }
-
-            y += 1;
-        }
-
-        buffer
-    };
-
-    pub fn new(driver: &mut ScreenDriver) -> Box<dyn Program> {
-        let mut prng_seed = [0_u8; 16];
-
-        let mut trng = Trng::new(
-            peripherals::trng(),
-            SampleMode::VonNeumann,
-            RetryCount::default(),
-        );
-
-        // use the TRNG to seed the PRNG
-        unsafe {
-            prng_seed.copy_from_slice(
-                [
-                    trng.next_u32().unwrap_unchecked().to_ne_bytes(),
-                    trng.next_u32().unwrap_unchecked().to_ne_bytes(),
-                    trng.next_u32().unwrap_unchecked().to_ne_bytes(),
-                    trng.next_u32().unwrap_unchecked().to_ne_bytes(),
-                ]
-                .flatten(),
-            );
-        }
+    const GROUND_LAYER: usize = 0;
+    const DROP_LAYER: usize = 1;
 
-        // disable TRNG
-        trng.release_disabled();
+    pub fn new(driver: &mut ScreenDriver<WIDTH, HEIGHT>) -> Box<dyn Program<WIDTH, HEIGHT>> {
+        let prng = seeded_rng();
 
-        let prng = SmallRng::from_seed(prng_seed);
+        // drops still fall at 64 pixels per second
+        driver.set_target_frame_rate(FrameRate::Fps128);
 
-        driver.set_target_frame_rate(FrameRate::Fps64);
+        let mut layers = LayerStack::new();
+        layers.layer_mut(Self::DROP_LAYER).blend_mode = BlendMode::Max;
 
         Box::new(Self {
             rng: prng,
-            raindrop_lines: Default::default(),
+            layers,
+            raindrop_lines: Vec::new(),
+            width: 0,
+            height: 0,
             line_shift: 0,
+            substep: 0,
         })
     }
 
-    fn spawn_drops(&mut self, driver: &mut ScreenDriver) {
-        for y in 0..Framebuffer::HEIGHT {
+    // the first column of the ground, inclusive
+    fn ground_level(&self) -> usize {
+        self.width.saturating_sub(Self::GROUND_DEPTH)
+    }
+
+    // starts over whenever the size that's drawn in has changed
+    fn fit_layout(&mut self, width: usize, height: usize) {
+        if self.width == width && self.height == height {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.line_shift = 0;
+        self.substep = 0;
+        self.raindrop_lines.clear();
+        self.raindrop_lines
+            .resize_with(width, || RaindropLine::with_capacity(height));
+    }
+
+    fn spawn_drops(&mut self) {
+        for y in 0..self.height {
             if self.rng.next_u32() <= Self::RAINDROP_FREQUENCY {
                 unsafe {
                     self.raindrop_lines
                         .get_mut(self.line_shift)
                         .unwrap_unchecked()
-                        .push(Raindrop::new(y as usize));
+                        .push(Raindrop::new(y));
                 }
             }
-
-            driver.drive_mid_render();
         }
     }
 
-    fn random_splashes(&mut self, driver: &mut ScreenDriver) {
+    fn random_splashes(&mut self) {
         let mut x = self.line_shift;
-        for _ in Self::GROUND_LEVEL..Framebuffer::WIDTH {
-            for raindrop in unsafe {
-                self.raindrop_lines
-                    .get_mut(x)
-                    .unwrap_unchecked()
-                    .get_slice_mut()
-            } {
+        for _ in self.ground_level()..self.width {
+            for raindrop in unsafe { self.raindrop_lines.get_mut(x).unwrap_unchecked().iter_mut() }
+            {
                 match raindrop.state {
                     RaindropState::Falling => {
                         if self.rng.next_u32() > Self::SPLASH_FREQUENCY {
@@ -146,18 +139,16 @@ impl Rain {
             }
 
             x += 1;
-            if x >= Framebuffer::WIDTH {
+            if x >= self.width {
                 x = 0;
             }
-
-            driver.drive_mid_render();
         }
     }
 
-    fn force_splashes(&mut self, driver: &mut ScreenDriver) {
+    fn force_splashes(&mut self) {
         let last_line_idx = match self.line_shift.checked_sub(1) {
             Some(val) => val,
-            None => Framebuffer::WIDTH - 1,
+            None => self.width - 1,
         };
 
         unsafe {
@@ -165,59 +156,66 @@ impl Rain {
                 .raindrop_lines
                 .get_mut(last_line_idx)
                 .unwrap_unchecked()
-                .get_slice_mut()
+                .iter_mut()
             {
                 raindrop.state = RaindropState::Splashing {
-                    splash_x: Framebuffer::WIDTH - 1,
+                    splash_x: self.width - 1,
                     frame: 0,
                 };
             }
         }
+    }
 
-        driver.drive_mid_render();
+    fn draw_ground(&mut self) {
+        let ground_level = self.ground_level() as i32;
+        let layer = self.layers.layer_mut(Self::GROUND_LAYER);
+
+        layer.clear();
+        layer.canvas.fill_rect(
+            ground_level,
+            0,
+            Self::GROUND_DEPTH as i32,
+            self.height as i32,
+            Self::GROUND_COLOR,
+        );
     }
 
-    fn rasterize_drops(&mut self, driver: &mut ScreenDriver) {
+    fn rasterize_drops(&mut self) {
+        let layer = self.layers.layer_mut(Self::DROP_LAYER);
+        layer.clear();
+        let canvas = &mut layer.canvas;
+
         let mut falling_x = self.line_shift;
+        // the way the falling drops have come towards the next pixel
+        let fall_offset = SUBPIXEL_ONE * self.substep as i32 / Self::SUBSTEPS as i32;
+        // splashes move on once the lines get shifted
+        let last_substep = self.substep + 1 == Self::SUBSTEPS;
 
         for line in &mut self.raindrop_lines {
-            for drop in line.get_slice_mut() {
+            for drop in line.iter_mut() {
                 match &mut drop.state {
                     RaindropState::Falling => {
-                        driver.framebuffer.back_buffer.set_led_adjusted(
-                            falling_x,
-                            drop.y,
+                        canvas.draw_dot(
+                            subpixels(falling_x as i32) + fall_offset,
+                            subpixels(drop.y as i32),
                             Self::RAINDROP_COLOR,
                         );
                     }
                     RaindropState::Splashing { splash_x, frame } => {
-                        // these may be out of bounds, so only attempt to set the pixels
+                        // these may be out of bounds, and get clipped
+                        let splash_x = *splash_x as i32;
+                        let drop_y = drop.y as i32;
+
                         match frame {
                             0 => {
-                                driver.framebuffer.back_buffer.try_set_led_adjusted(
-                                    *splash_x - 1,
-                                    drop.y - 1,
-                                    Self::RAINDROP_COLOR,
-                                );
-                                driver.framebuffer.back_buffer.try_set_led_adjusted(
-                                    *splash_x - 1,
-                                    drop.y + 1,
-                                    Self::RAINDROP_COLOR,
-                                );
-                                *frame += 1;
+                                canvas.draw_pixel(splash_x - 1, drop_y - 1, Self::RAINDROP_COLOR);
+                                canvas.draw_pixel(splash_x - 1, drop_y + 1, Self::RAINDROP_COLOR);
+                                *frame += last_substep as u8;
                             }
                             1 => {
-                                driver.framebuffer.back_buffer.try_set_led_adjusted(
-                                    *splash_x,
-                                    drop.y - 1,
-                                    Self::RAINDROP_COLOR,
-                                );
-                                driver.framebuffer.back_buffer.try_set_led_adjusted(
-                                    *splash_x,
-                                    drop.y + 1,
-                                    Self::RAINDROP_COLOR,
-                                );
-                                *frame += 1;
+                                canvas.draw_pixel(splash_x, drop_y - 1, Self::RAINDROP_COLOR);
+                                canvas.draw_pixel(splash_x, drop_y + 1, Self::RAINDROP_COLOR);
+                                *frame += last_substep as u8;
                             }
                             _ => {
                                 // finished splash animation
@@ -225,30 +223,39 @@ impl Rain {
                         }
                     }
                 }
-
-                driver.drive_mid_render();
             }
 
             falling_x += 1;
-            if falling_x >= Framebuffer::WIDTH {
+            if falling_x >= self.width {
                 falling_x = 0;
             }
         }
     }
 }
 
-impl Program for Rain {
-    fn render(&mut self, driver: &mut ScreenDriver) {
-        driver.framebuffer.back_buffer = Self::BASE_FRAME;
-        driver.drive_mid_render();
+impl<const WIDTH: usize, const HEIGHT: usize> Program<WIDTH, HEIGHT> for Rain<WIDTH, HEIGHT> {
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>) {
+        self.layers.set_orientation(driver.orientation());
+        self.fit_layout(driver.width(), driver.height());
 
-        self.spawn_drops(driver);
-        self.random_splashes(driver);
-        self.force_splashes(driver);
-        self.rasterize_drops(driver);
+        self.draw_ground();
+        if self.substep == 0 {
+            self.spawn_drops();
+            self.random_splashes();
+            self.force_splashes();
+        }
+        self.rasterize_drops();
+
+        self.layers.composite(&mut driver.framebuffer.back_buffer);
+
+        self.substep += 1;
+        if self.substep < Self::SUBSTEPS {
+            return;
+        }
+        self.substep = 0;
 
         self.line_shift += 1;
-        if self.line_shift >= Framebuffer::WIDTH {
+        if self.line_shift >= self.width {
             self.line_shift = 0;
         }
 
@@ -260,3 +267,79 @@ impl Program for Rain {
         }
     }
 }
+
+#[cfg(target_arch = "arm")]
+fn seeded_rng() -> SmallRng {
+    let mut prng_seed = [0_u8; 16];
+
+    let mut trng = Trng::new(
+        peripherals::trng(),
+        SampleMode::VonNeumann,
+        RetryCount::default(),
+    );
+
+    // use the TRNG to seed the PRNG
+    unsafe {
+        prng_seed.copy_from_slice(
+            [
+                trng.next_u32().unwrap_unchecked().to_ne_bytes(),
+                trng.next_u32().unwrap_unchecked().to_ne_bytes(),
+                trng.next_u32().unwrap_unchecked().to_ne_bytes(),
+                trng.next_u32().unwrap_unchecked().to_ne_bytes(),
+            ]
+            .flatten(),
+        );
+    }
+
+    // disable TRNG
+    trng.release_disabled();
+
+    SmallRng::from_seed(prng_seed)
+}
+
+// host runs get the same drops every time, so they can be compared
+#[cfg(not(target_arch = "arm"))]
+fn seeded_rng() -> SmallRng {
+    SmallRng::seed_from_u64(0x5A5A_5A5A)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{FrameHandoff, Orientation, Rotation, PANEL_HEIGHT, PANEL_WIDTH};
+
+    type PanelRain = Rain<PANEL_WIDTH, PANEL_HEIGHT>;
+
+    #[test]
+    fn drops_land_on_the_ground_when_turned() {
+        let handoff = Box::leak(Box::new(FrameHandoff::new(FrameRate::Fps64.rtc_mask())));
+        let mut driver: ScreenDriver = ScreenDriver::with_handoff(handoff);
+        driver.set_orientation(Orientation {
+            rotation: Rotation::Clockwise90,
+            mirror: false,
+        });
+        let mut rain = Rain::new(&mut driver);
+
+        let (width, height) = (driver.width(), driver.height());
+        let ground_level = width - PanelRain::GROUND_DEPTH;
+        let mut splashed = false;
+
+        for _ in 0..200 {
+            rain.render(&mut driver);
+            let back_buffer = &driver.framebuffer.back_buffer;
+
+            // the ground covers every row, and drops only ever brighten it
+            for y in 0..height {
+                for x in ground_level..width {
+                    let color = back_buffer.get_led_adjusted(x, y);
+                    assert!(
+                        color != AdjustedColor::default() && color.r >= PanelRain::GROUND_COLOR.r
+                    );
+                    splashed |= color == PanelRain::RAINDROP_COLOR;
+                }
+            }
+        }
+
+        assert!(splashed);
+    }
+}
@@ -0,0 +1,44 @@
+use alloc::boxed::Box;
+
+use crate::color::{Color, WHITE};
+use crate::led_driver::{FrameRate, ScreenDriver};
+use crate::program::Program;
+
+/// A still test pattern for tuning [`crate::color::LED_CALIBRATION`]. The top half is a gray ramp,
+/// which should look neutral at every step once the scales and offsets are right. The bottom half
+/// shows the channels on their own at full brightness, to compare their maximum currents.
+pub struct WhiteBalance;
+
+impl WhiteBalance {
+    pub fn new<const WIDTH: usize, const HEIGHT: usize>(
+        driver: &mut ScreenDriver<WIDTH, HEIGHT>,
+    ) -> Box<dyn Program<WIDTH, HEIGHT>> {
+        driver.set_target_frame_rate(FrameRate::Fps1);
+
+        Box::new(Self)
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Program<WIDTH, HEIGHT> for WhiteBalance {
+    fn render(&mut self, driver: &mut ScreenDriver<WIDTH, HEIGHT>) {
+        let back_buffer = &mut driver.framebuffer.back_buffer;
+        let width = back_buffer.width();
+        let height = back_buffer.height();
+
+        for x in 0..width {
+            let level = (((x + 1) * u8::MAX as usize) / width) as u8;
+            let gray = Color::from_rgb(level, level, level);
+
+            let channel_color = match (x * 3) / width {
+                0 => Color::from_rgb(WHITE.r, 0, 0),
+                1 => Color::from_rgb(0, WHITE.g, 0),
+                _ => Color::from_rgb(0, 0, WHITE.b),
+            };
+
+            for y in 0..height {
+                let color = if y < height / 2 { gray } else { channel_color };
+                back_buffer.set_led(x, y, color);
+            }
+        }
+    }
+}
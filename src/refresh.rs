@@ -0,0 +1,164 @@
+use core::mem::MaybeUninit;
+
+use cortex_m::peripheral::NVIC;
+use teensy4_bsp::board::PERCLK_FREQUENCY;
+use teensy4_bsp::ral::{self, interrupt, modify_reg, write_reg, Interrupt};
+
+use crate::framebuffer::FrameHandoff;
+use crate::led_driver::{FrameRate, ShiftEngine};
+use crate::output::{DmaOutput, FlexIoOutput, GpioOutput, DMA_STEPS_PER_HALF};
+use crate::peripherals;
+
+// The matrix is refreshed from PIT channel 0, which runs a whole shift cycle of the shift engine
+// per period. In FlexIO mode, the PIT interrupt only takes the first step of the shift cycle, and
+// the rest are taken from the FlexIO1 interrupt whenever the clock pulses of a step are over. In
+// DMA mode, the same timer paces the DMA instead, and the engine is stepped in batches from the DMA
+// interrupt whenever half of the stream needs to be refilled. The render code never touches the
+// engine directly, it only hands frames over through FRAME_HANDOFF.
+
+pub static FRAME_HANDOFF: FrameHandoff = FrameHandoff::new(FrameRate::Fps64.rtc_mask());
+
+// the engines that are stepped directly by the PIT interrupt
+enum PitEngine {
+    Gpio(ShiftEngine<GpioOutput>),
+    FlexIo(ShiftEngine<FlexIoOutput>),
+}
+
+impl PitEngine {
+    fn step_shift_cycle(&mut self) {
+        match self {
+            PitEngine::Gpio(engine) => engine.step_shift_cycle(),
+            PitEngine::FlexIo(engine) => unsafe {
+                FLEXIO_STEPS_LEFT = ShiftEngine::<FlexIoOutput>::SHIFT_COUNT;
+                step_flexio(engine);
+            },
+        }
+    }
+}
+
+// the steps of the current shift cycle that the FlexIO1 interrupt still has to take
+static mut FLEXIO_STEPS_LEFT: u32 = 0;
+
+// takes steps until one of them has to wait for its clock pulses, which happens for every step
+// except the ones holding a bit plane
+unsafe fn step_flexio(engine: &mut ShiftEngine<FlexIoOutput>) {
+    while FLEXIO_STEPS_LEFT > 0 {
+        FLEXIO_STEPS_LEFT -= 1;
+        engine.step();
+
+        if engine.output().pulses_pending() {
+            return;
+        }
+    }
+}
+
+// only ever accessed by the PIT interrupt once the timer is running
+static mut ENGINE: MaybeUninit<PitEngine> = MaybeUninit::uninit();
+// only ever accessed by the DMA interrupt once the stream is running
+static mut DMA_ENGINE: MaybeUninit<ShiftEngine<DmaOutput>> = MaybeUninit::uninit();
+
+/// The time each shift engine step gets on average. In DMA mode, this is the time between two
+/// ticks, which is half a step.
+pub const STEP_PERIOD_US: u32 = 1;
+/// The time between two PIT interrupts. Each one runs a whole shift cycle of steps at once, which
+/// keeps the interrupt overhead to once per latch.
+pub const SHIFT_CYCLE_PERIOD_US: u32 = STEP_PERIOD_US * ShiftEngine::<GpioOutput>::SHIFT_COUNT;
+
+pub fn start(engine: ShiftEngine<GpioOutput>) {
+    start_pit(PitEngine::Gpio(engine));
+}
+
+pub fn start_flexio(engine: ShiftEngine<FlexIoOutput>) {
+    start_pit(PitEngine::FlexIo(engine));
+
+    unsafe {
+        NVIC::unmask(Interrupt::FLEXIO1);
+    }
+}
+
+fn start_pit(engine: PitEngine) {
+    unsafe {
+        ENGINE.write(engine);
+    }
+
+    start_step_timer(SHIFT_CYCLE_PERIOD_US, true);
+
+    unsafe {
+        NVIC::unmask(Interrupt::PIT);
+        cortex_m::interrupt::enable();
+    }
+}
+
+pub fn start_dma(mut engine: ShiftEngine<DmaOutput>) {
+    for half in 0..2 {
+        engine.output_mut().seek(half);
+        for _ in 0..DMA_STEPS_PER_HALF {
+            engine.step();
+        }
+    }
+    engine.output_mut().start_stream();
+
+    unsafe {
+        DMA_ENGINE.write(engine);
+    }
+
+    start_step_timer(STEP_PERIOD_US, false);
+
+    unsafe {
+        NVIC::unmask(Interrupt::DMA0_DMA16);
+        cortex_m::interrupt::enable();
+    }
+}
+
+fn start_step_timer(period_us: u32, interrupt_enable: bool) {
+    // the PIT clock gate is enabled by prepare_clocks_and_power, and runs off of PERCLK
+    let pit = peripherals::pit();
+    modify_reg!(ral::pit, pit, MCR, MDIS: MDIS_0);
+    write_reg!(
+        ral::pit::timer,
+        &pit.TIMER[0],
+        LDVAL,
+        (PERCLK_FREQUENCY / 1_000_000) * period_us - 1
+    );
+    write_reg!(ral::pit::timer, &pit.TIMER[0], TFLG, TIF: 1);
+    write_reg!(
+        ral::pit::timer,
+        &pit.TIMER[0],
+        TCTRL,
+        TIE: interrupt_enable as u32,
+        TEN: 1
+    );
+}
+
+#[cortex_m_rt::interrupt]
+fn PIT() {
+    write_reg!(ral::pit::timer, &peripherals::pit().TIMER[0], TFLG, TIF: 1);
+
+    unsafe {
+        ENGINE.assume_init_mut().step_shift_cycle();
+    }
+}
+
+#[cortex_m_rt::interrupt]
+fn FLEXIO1() {
+    // both interrupts have the same priority, so they never preempt each other
+    unsafe {
+        if let PitEngine::FlexIo(engine) = ENGINE.assume_init_mut() {
+            if engine.output_mut().finish_pulses() {
+                step_flexio(engine);
+            }
+        }
+    }
+}
+
+#[cortex_m_rt::interrupt]
+fn DMA0_DMA16() {
+    let engine = unsafe { DMA_ENGINE.assume_init_mut() };
+    engine.output_mut().clear_interrupt();
+
+    let half = engine.output().finished_half();
+    engine.output_mut().seek(half);
+    for _ in 0..DMA_STEPS_PER_HALF {
+        engine.step();
+    }
+}
@@ -0,0 +1,84 @@
+use crate::alarm::{AlarmClock, MAX_ALARMS};
+use crate::low_power::{LowPowerDomain, PlatformLowPower};
+use crate::time::{DstRule, HourFormat, TimeZone, SECONDS_PER_MINUTE};
+
+// The settings that can be changed with the button are kept in the SNVS low power general purpose
+// register after the alarms, so they survive resets like the SRTC time does. A register that has
+// been cleared by a power loss reads as UTC, with 12 hour time and the default sunrise.
+
+const SETTINGS_REGISTER: usize = MAX_ALARMS;
+
+// the UTC offset is stored in quarter hours, which covers every time zone in use
+const UTC_OFFSET_SHIFT: u32 = 0;
+const DST_RULE_SHIFT: u32 = 8;
+const TWENTY_FOUR_HOUR_BIT: u32 = 0b1 << 12;
+const SUNRISE_MINUTES_SHIFT: u32 = 16;
+// zero minutes turn the sunrise off, so a cleared register needs this to tell them apart
+const SUNRISE_SET_BIT: u32 = 0b1 << 31;
+
+const QUARTER_HOUR_SECONDS: i32 = 15 * SECONDS_PER_MINUTE as i32;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Settings {
+    /// The time zone that the clock and the alarms go by.
+    pub time_zone: TimeZone,
+    /// How the clock shows the hours.
+    pub hour_format: HourFormat,
+    /// How long before each alarm the sunrise starts. Zero turns the sunrise off.
+    pub sunrise_minutes: u32,
+}
+
+impl Settings {
+    pub fn load() -> Settings {
+        Self::decode(PlatformLowPower::open().register(SETTINGS_REGISTER))
+    }
+
+    pub fn save(&self) {
+        PlatformLowPower::open().set_register(SETTINGS_REGISTER, self.encode());
+    }
+
+    fn encode(&self) -> u32 {
+        let quarter_hours = (self.time_zone.utc_offset_seconds / QUARTER_HOUR_SECONDS) as i8;
+        let dst_rule = match self.time_zone.dst_rule {
+            DstRule::None => 0,
+            DstRule::Eu => 1,
+            DstRule::Us => 2,
+        };
+
+        let hour_format = match self.hour_format {
+            HourFormat::Twelve => 0,
+            HourFormat::TwentyFour => TWENTY_FOUR_HOUR_BIT,
+        };
+
+        ((quarter_hours as u8 as u32) << UTC_OFFSET_SHIFT)
+            | (dst_rule << DST_RULE_SHIFT)
+            | hour_format
+            | ((self.sunrise_minutes as u8 as u32) << SUNRISE_MINUTES_SHIFT)
+            | SUNRISE_SET_BIT
+    }
+
+    fn decode(value: u32) -> Settings {
+        let quarter_hours = (value >> UTC_OFFSET_SHIFT) as u8 as i8;
+        let dst_rule = match (value >> DST_RULE_SHIFT) & 0b11 {
+            1 => DstRule::Eu,
+            2 => DstRule::Us,
+            _ => DstRule::None,
+        };
+        let hour_format = if value & TWENTY_FOUR_HOUR_BIT == 0 {
+            HourFormat::Twelve
+        } else {
+            HourFormat::TwentyFour
+        };
+        let sunrise_minutes = if value & SUNRISE_SET_BIT == 0 {
+            AlarmClock::DEFAULT_SUNRISE_MINUTES
+        } else {
+            (value >> SUNRISE_MINUTES_SHIFT) as u8 as u32
+        };
+
+        Settings {
+            time_zone: TimeZone::new(quarter_hours as i32 * QUARTER_HOUR_SECONDS, dst_rule),
+            hour_format,
+            sunrise_minutes,
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::color::{AdjustedColor, AdjustedColorRgba, BlendMode, Color, ColorRgba};
+use crate::framebuffer::BackBuffer;
+
+const TRANSPARENT: AdjustedColorRgba = AdjustedColorRgba {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0,
+};
+
+/// A small image that can be blitted onto the back buffer. Sprites are meant to be built at compile
+/// time, and already hold colors adjusted for the LEDs.
+#[derive(Copy, Clone)]
+pub struct Sprite<const WIDTH: usize, const HEIGHT: usize> {
+    pixels: [[AdjustedColorRgba; WIDTH]; HEIGHT],
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> Sprite<WIDTH, HEIGHT> {
+    pub const EMPTY: Self = Self {
+        pixels: [[TRANSPARENT; WIDTH]; HEIGHT],
+    };
+
+    /// Every non-zero entry of the stencil gets the color, the rest is transparent.
+    pub const fn from_stencil(stencil: [[u8; WIDTH]; HEIGHT], color: AdjustedColor) -> Self {
+        let mut sprite = Self::EMPTY;
+
+        let mut y = 0;
+        while y < HEIGHT {
+            let mut x = 0;
+            while x < WIDTH {
+                if stencil[y][x] != 0 {
+                    sprite.pixels[y][x] = AdjustedColorRgba::from_color(color, u8::MAX);
+                }
+                x += 1;
+            }
+            y += 1;
+        }
+
+        sprite
+    }
+
+    /// Builds a sprite for every stencil, all with the same color.
+    pub const fn array_from_stencils<const COUNT: usize>(
+        stencils: [[[u8; WIDTH]; HEIGHT]; COUNT],
+        color: AdjustedColor,
+    ) -> [Self; COUNT] {
+        let mut sprites = [Self::EMPTY; COUNT];
+
+        let mut i = 0;
+        while i < COUNT {
+            sprites[i] = Self::from_stencil(stencils[i], color);
+            i += 1;
+        }
+
+        sprites
+    }
+
+    /// Adjusts every color for the LEDs. Pixels that match the color key are transparent.
+    pub const fn from_colors(colors: [[Color; WIDTH]; HEIGHT], color_key: Option<Color>) -> Self {
+        let mut sprite = Self::EMPTY;
+
+        let mut y = 0;
+        while y < HEIGHT {
+            let mut x = 0;
+            while x < WIDTH {
+                let color = colors[y][x];
+                let transparent = match color_key {
+                    Some(key) => color.r == key.r && color.g == key.g && color.b == key.b,
+                    None => false,
+                };
+
+                if !transparent {
+                    sprite.pixels[y][x] =
+                        AdjustedColorRgba::from_color(color.adjust_for_led(), u8::MAX);
+                }
+                x += 1;
+            }
+            y += 1;
+        }
+
+        sprite
+    }
+
+    /// Adjusts every color for the LEDs, and keeps the alpha of each pixel.
+    pub const fn from_rgba_colors(colors: [[ColorRgba; WIDTH]; HEIGHT]) -> Self {
+        let mut sprite = Self::EMPTY;
+
+        let mut y = 0;
+        while y < HEIGHT {
+            let mut x = 0;
+            while x < WIDTH {
+                sprite.pixels[y][x] = colors[y][x].adjust_for_led();
+                x += 1;
+            }
+            y += 1;
+        }
+
+        sprite
+    }
+
+    pub const fn width(&self) -> usize {
+        WIDTH
+    }
+
+    pub const fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    pub const fn pixel(&self, x: usize, y: usize) -> AdjustedColorRgba {
+        self.pixels[y][x]
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct BlitOptions<'a> {
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// Pixels with the first color of a pair are drawn with the second one instead, which lets one
+    /// sprite be drawn in different colors.
+    pub palette: &'a [(AdjustedColor, AdjustedColorRgba)],
+    pub blend_mode: BlendMode,
+}
+
+impl BlitOptions<'_> {
+    pub const DEFAULT: BlitOptions<'static> = BlitOptions {
+        flip_horizontal: false,
+        flip_vertical: false,
+        palette: &[],
+        blend_mode: BlendMode::Normal,
+    };
+
+    fn substitute(&self, pixel: AdjustedColorRgba) -> AdjustedColorRgba {
+        let color = pixel.color();
+
+        match self.palette.iter().find(|(from, _)| *from == color) {
+            // the substitute is only as opaque as the pixel it replaces
+            Some(&(_, to)) => AdjustedColorRgba {
+                a: ((to.a as u16 * pixel.a as u16) / u8::MAX as u16) as u8,
+                ..to
+            },
+            None => pixel,
+        }
+    }
+}
+
+impl Default for BlitOptions<'_> {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<const WIDTH: usize, const HEIGHT: usize> BackBuffer<WIDTH, HEIGHT> {
+    /// Draws the sprite with its top left corner at the given position. Parts of the sprite that
+    /// are off the panel get clipped.
+    pub fn blit<const SPRITE_WIDTH: usize, const SPRITE_HEIGHT: usize>(
+        &mut self,
+        sprite: &Sprite<SPRITE_WIDTH, SPRITE_HEIGHT>,
+        x: i32,
+        y: i32,
+        options: &BlitOptions,
+    ) {
+        // only visit the part of the sprite that overlaps the panel
+        let start_x = x.saturating_neg().clamp(0, SPRITE_WIDTH as i32) as usize;
+        let end_x = (self.width() as i32)
+            .saturating_sub(x)
+            .clamp(0, SPRITE_WIDTH as i32) as usize;
+        let start_y = y.saturating_neg().clamp(0, SPRITE_HEIGHT as i32) as usize;
+        let end_y = (self.height() as i32)
+            .saturating_sub(y)
+            .clamp(0, SPRITE_HEIGHT as i32) as usize;
+
+        for sprite_y in start_y..end_y {
+            let source_y = if options.flip_vertical {
+                SPRITE_HEIGHT - 1 - sprite_y
+            } else {
+                sprite_y
+            };
+
+            for sprite_x in start_x..end_x {
+                let source_x = if options.flip_horizontal {
+                    SPRITE_WIDTH - 1 - sprite_x
+                } else {
+                    sprite_x
+                };
+
+                let pixel = options.substitute(sprite.pixel(source_x, source_y));
+                if pixel.a == 0 {
+                    continue;
+                }
+
+                let led_x = (x + sprite_x as i32) as usize;
+                let led_y = (y + sprite_y as i32) as usize;
+
+                if pixel.a == u8::MAX && options.blend_mode == BlendMode::Normal {
+                    self.set_led_adjusted(led_x, led_y, pixel.color());
+                } else {
+                    self.blend_led(led_x, led_y, pixel, options.blend_mode);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::{PANEL_HEIGHT, PANEL_WIDTH};
+
+    const RED: Color = Color::from_rgb(255, 0, 0);
+    const GREEN: Color = Color::from_rgb(0, 255, 0);
+    const BLUE: Color = Color::from_rgb(0, 0, 255);
+    const KEY: Color = Color::from_rgb(255, 0, 255);
+
+    // every opaque pixel has a color of its own, so flips can be told apart
+    const SPRITE: Sprite<3, 2> =
+        Sprite::from_colors([[RED, GREEN, BLUE], [KEY, BLUE, RED]], Some(KEY));
+
+    fn blit(x: i32, y: i32, options: &BlitOptions) -> BackBuffer {
+        let mut buffer: BackBuffer = BackBuffer::default();
+        buffer.blit(&SPRITE, x, y, options);
+
+        buffer
+    }
+
+    // the rows of the buffer from the given position on, where None is left untouched
+    fn assert_shows(buffer: &BackBuffer, x: usize, y: usize, rows: &[[Option<Color>; 3]]) {
+        for (row_y, row) in rows.iter().enumerate() {
+            for (row_x, color) in row.iter().enumerate() {
+                let expected = color.map_or(AdjustedColor::default(), Color::adjust_for_led);
+                assert!(
+                    buffer.get_led_adjusted(x + row_x, y + row_y) == expected,
+                    "({}, {}) has the wrong color",
+                    x + row_x,
+                    y + row_y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flips_mirror_the_sprite_in_place() {
+        let (r, g, b) = (Some(RED), Some(GREEN), Some(BLUE));
+
+        let buffer = blit(1, 1, &BlitOptions::DEFAULT);
+        assert_shows(&buffer, 1, 1, &[[r, g, b], [None, b, r]]);
+
+        let horizontal = BlitOptions {
+            flip_horizontal: true,
+            ..BlitOptions::DEFAULT
+        };
+        assert_shows(&blit(1, 1, &horizontal), 1, 1, &[[b, g, r], [r, b, None]]);
+
+        let vertical = BlitOptions {
+            flip_vertical: true,
+            ..BlitOptions::DEFAULT
+        };
+        assert_shows(&blit(1, 1, &vertical), 1, 1, &[[None, b, r], [r, g, b]]);
+
+        let both = BlitOptions {
+            flip_horizontal: true,
+            flip_vertical: true,
+            ..BlitOptions::DEFAULT
+        };
+        assert_shows(&blit(1, 1, &both), 1, 1, &[[r, b, None], [b, g, r]]);
+    }
+
+    #[test]
+    fn palette_substitutes_matching_colors() {
+        let white = Color::from_rgb(255, 255, 255);
+        let palette = [(
+            RED.adjust_for_led(),
+            AdjustedColorRgba::from_color(white.adjust_for_led(), u8::MAX),
+        )];
+        let options = BlitOptions {
+            palette: &palette,
+            ..BlitOptions::DEFAULT
+        };
+
+        let (w, g, b) = (Some(white), Some(GREEN), Some(BLUE));
+        assert_shows(&blit(0, 0, &options), 0, 0, &[[w, g, b], [None, b, w]]);
+    }
+
+    #[test]
+    fn sprites_get_clipped_at_the_edges() {
+        let (r, b) = (Some(RED), Some(BLUE));
+
+        // only the bottom right of the sprite is left
+        let buffer = blit(-1, -1, &BlitOptions::DEFAULT);
+        assert_shows(&buffer, 0, 0, &[[b, r, None], [None, None, None]]);
+
+        // and only the top left here
+        let buffer = blit(
+            PANEL_WIDTH as i32 - 1,
+            PANEL_HEIGHT as i32 - 1,
+            &BlitOptions::DEFAULT,
+        );
+        assert!(buffer.get_led_adjusted(PANEL_WIDTH - 1, PANEL_HEIGHT - 1) == RED.adjust_for_led());
+        assert_shows(
+            &buffer,
+            PANEL_WIDTH - 3,
+            PANEL_HEIGHT - 2,
+            &[[None, None, None], [None, None, r]],
+        );
+
+        // nothing is left of these
+        for (x, y) in [(i32::MIN, i32::MIN), (i32::MAX, i32::MAX), (-3, 0), (0, -2)] {
+            let buffer = blit(x, y, &BlitOptions::DEFAULT);
+            assert_shows(&buffer, 0, 0, &[[None, None, None], [None, None, None]]);
+        }
+    }
+}
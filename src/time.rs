@@ -0,0 +1,225 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, Weekday};
+
+use crate::low_power::{LowPowerDomain, PlatformLowPower};
+use crate::settings::Settings;
+
+// The SRTC keeps counting seconds on the coin cell while the board is off. The seconds are kept in
+// UTC since the start of 1970, and get turned into local time with the time zone from the settings
+// wherever they are shown or compared against a time of day.
+
+pub const SECONDS_PER_MINUTE: u32 = 60;
+pub const SECONDS_PER_HOUR: u32 = 60 * SECONDS_PER_MINUTE;
+pub const SECONDS_PER_DAY: u32 = 24 * SECONDS_PER_HOUR;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum DstRule {
+    /// The offset stays the same all year.
+    None,
+    /// One hour ahead from 01:00 UTC on the last Sunday of March, until 01:00 UTC on the last
+    /// Sunday of October.
+    Eu,
+    /// One hour ahead from 02:00 local time on the second Sunday of March, until 02:00 local time
+    /// on the first Sunday of November.
+    Us,
+}
+
+/// How the hours of the day are shown.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum HourFormat {
+    /// 1 to 12, with "am" or "pm" below the hours.
+    Twelve,
+    /// 00 to 23.
+    TwentyFour,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct TimeZone {
+    /// The offset from UTC outside of daylight saving time.
+    pub utc_offset_seconds: i32,
+    pub dst_rule: DstRule,
+}
+
+impl TimeZone {
+    pub const UTC: TimeZone = TimeZone::new(0, DstRule::None);
+    pub const WESTERN_EUROPE: TimeZone = TimeZone::new(0, DstRule::Eu);
+    pub const CENTRAL_EUROPE: TimeZone = TimeZone::new(SECONDS_PER_HOUR as i32, DstRule::Eu);
+    pub const EASTERN_EUROPE: TimeZone = TimeZone::new(2 * SECONDS_PER_HOUR as i32, DstRule::Eu);
+    pub const US_EASTERN: TimeZone = TimeZone::new(-5 * SECONDS_PER_HOUR as i32, DstRule::Us);
+    pub const US_CENTRAL: TimeZone = TimeZone::new(-6 * SECONDS_PER_HOUR as i32, DstRule::Us);
+    pub const US_MOUNTAIN: TimeZone = TimeZone::new(-7 * SECONDS_PER_HOUR as i32, DstRule::Us);
+    pub const US_PACIFIC: TimeZone = TimeZone::new(-8 * SECONDS_PER_HOUR as i32, DstRule::Us);
+
+    const DST_SECONDS: i32 = SECONDS_PER_HOUR as i32;
+
+    pub const fn new(utc_offset_seconds: i32, dst_rule: DstRule) -> Self {
+        Self {
+            utc_offset_seconds,
+            dst_rule,
+        }
+    }
+
+    pub fn is_dst(self, utc_seconds: u32) -> bool {
+        let utc_seconds = utc_seconds as i64;
+        let Some(date) = NaiveDateTime::from_timestamp_opt(utc_seconds, 0) else {
+            return false;
+        };
+        let year = date.year();
+        let standard_offset = self.utc_offset_seconds as i64;
+        let dst_seconds = Self::DST_SECONDS as i64;
+
+        let (start, end) = match self.dst_rule {
+            DstRule::None => return false,
+            DstRule::Eu => (
+                last_sunday_timestamp(year, 3) + SECONDS_PER_HOUR as i64,
+                last_sunday_timestamp(year, 10) + SECONDS_PER_HOUR as i64,
+            ),
+            DstRule::Us => (
+                nth_sunday_timestamp(year, 3, 2) + 2 * SECONDS_PER_HOUR as i64 - standard_offset,
+                nth_sunday_timestamp(year, 11, 1) + 2 * SECONDS_PER_HOUR as i64
+                    - standard_offset
+                    - dst_seconds,
+            ),
+        };
+
+        (start..end).contains(&utc_seconds)
+    }
+
+    /// The offset from UTC at the given time, including daylight saving time.
+    pub fn offset_at(self, utc_seconds: u32) -> i32 {
+        if self.is_dst(utc_seconds) {
+            self.utc_offset_seconds + Self::DST_SECONDS
+        } else {
+            self.utc_offset_seconds
+        }
+    }
+
+    pub fn to_local_seconds(self, utc_seconds: u32) -> u32 {
+        utc_seconds.wrapping_add_signed(self.offset_at(utc_seconds))
+    }
+
+    /// Reverses [`TimeZone::to_local_seconds`]. Local times that happen twice when the clocks go
+    /// back are taken as the first one, and the ones skipped when the clocks go forward end up an
+    /// hour later.
+    pub fn to_utc_seconds(self, local_seconds: u32) -> u32 {
+        let standard = local_seconds.wrapping_add_signed(-self.utc_offset_seconds);
+        let daylight = standard.wrapping_add_signed(-Self::DST_SECONDS);
+
+        if self.is_dst(daylight) {
+            daylight
+        } else {
+            standard
+        }
+    }
+
+    pub fn to_local(self, utc_seconds: u32) -> DateTime<FixedOffset> {
+        let offset = FixedOffset::east_opt(self.offset_at(utc_seconds)).unwrap();
+        // every u32 timestamp is in range
+        let naive = NaiveDateTime::from_timestamp_opt(utc_seconds as i64, 0).unwrap();
+
+        DateTime::from_naive_utc_and_offset(naive, offset)
+    }
+}
+
+/// The current local date and time, for programs that want to show more than the time of day.
+/// Doesn't need an SRTC handle of the program's own.
+pub fn local_now() -> DateTime<FixedOffset> {
+    Settings::load()
+        .time_zone
+        .to_local(PlatformLowPower::open().time())
+}
+
+/// The day of the week for the given seconds since 1970, where Monday is 0.
+pub fn weekday(seconds: u32) -> u32 {
+    // the first of January 1970 was a Thursday
+    (seconds / SECONDS_PER_DAY + 3) % 7
+}
+
+// midnight UTC on the last Sunday of a month with 31 days
+fn last_sunday_timestamp(year: i32, month: u32) -> i64 {
+    let last_day = NaiveDate::from_ymd_opt(year, month, 31).unwrap();
+    let days_back = last_day.weekday().num_days_from_sunday() as i64;
+
+    midnight_timestamp(last_day) - days_back * SECONDS_PER_DAY as i64
+}
+
+// midnight UTC on the nth Sunday of a month, counting from 1
+fn nth_sunday_timestamp(year: i32, month: u32, n: u8) -> i64 {
+    midnight_timestamp(NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, n).unwrap())
+}
+
+fn midnight_timestamp(date: NaiveDate) -> i64 {
+    date.and_hms_opt(0, 0, 0).unwrap().timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Timelike;
+
+    use super::*;
+
+    fn utc_seconds(year: i32, month: u32, day: u32, hours: u32, minutes: u32) -> u32 {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hours, minutes, 0)
+            .unwrap()
+            .timestamp() as u32
+    }
+
+    #[test]
+    fn eu_clocks_change_at_one_utc() {
+        let zone = TimeZone::CENTRAL_EUROPE;
+
+        // the last Sundays of March and October 2024
+        assert!(!zone.is_dst(utc_seconds(2024, 3, 31, 0, 59)));
+        assert!(zone.is_dst(utc_seconds(2024, 3, 31, 1, 0)));
+        assert!(zone.is_dst(utc_seconds(2024, 10, 27, 0, 59)));
+        assert!(!zone.is_dst(utc_seconds(2024, 10, 27, 1, 0)));
+
+        let summer = utc_seconds(2024, 7, 1, 12, 0);
+        assert_eq!(zone.offset_at(summer), 2 * SECONDS_PER_HOUR as i32);
+        assert_eq!(zone.to_local(summer).hour(), 14);
+    }
+
+    #[test]
+    fn us_clocks_change_at_two_local() {
+        let zone = TimeZone::US_EASTERN;
+
+        // 02:00 EST on the second Sunday of March, and 02:00 EDT on the first Sunday of November
+        assert!(!zone.is_dst(utc_seconds(2024, 3, 10, 6, 59)));
+        assert!(zone.is_dst(utc_seconds(2024, 3, 10, 7, 0)));
+        assert!(zone.is_dst(utc_seconds(2024, 11, 3, 5, 59)));
+        assert!(!zone.is_dst(utc_seconds(2024, 11, 3, 6, 0)));
+
+        assert!(!TimeZone::UTC.is_dst(utc_seconds(2024, 7, 1, 12, 0)));
+    }
+
+    #[test]
+    fn local_seconds_go_back_to_utc() {
+        for zone in [TimeZone::CENTRAL_EUROPE, TimeZone::US_PACIFIC] {
+            for utc in [
+                utc_seconds(2024, 1, 15, 8, 30),
+                utc_seconds(2024, 7, 15, 8, 30),
+            ] {
+                assert_eq!(zone.to_utc_seconds(zone.to_local_seconds(utc)), utc);
+            }
+        }
+
+        let zone = TimeZone::CENTRAL_EUROPE;
+        // 02:30 is skipped when the clocks go forward, and ends up as 03:30
+        let skipped = utc_seconds(2024, 3, 31, 2, 30);
+        assert_eq!(
+            zone.to_utc_seconds(skipped),
+            utc_seconds(2024, 3, 31, 1, 30)
+        );
+        // 02:30 happens twice when the clocks go back, and the first one is taken
+        let twice = utc_seconds(2024, 10, 27, 2, 30);
+        assert_eq!(zone.to_utc_seconds(twice), utc_seconds(2024, 10, 27, 0, 30));
+    }
+
+    #[test]
+    fn weekdays_start_on_monday() {
+        // a Monday
+        assert_eq!(weekday(utc_seconds(2024, 1, 1, 12, 0)), 0);
+        assert_eq!(weekday(utc_seconds(2024, 1, 7, 23, 59)), 6);
+    }
+}